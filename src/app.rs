@@ -1,70 +1,186 @@
+use std::collections::VecDeque;
 use std::fs;
 use std::io;
-use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use termion::event::Key;
 use termion::input::MouseTerminal;
 use termion::screen::AlternateScreen;
 use tui::backend::TermionBackend;
 use tui::layout::Rect;
 use tui::Terminal;
 
-use crate::file::File;
+use crate::buffer::Buffer;
+use crate::color::ColorScheme;
+use crate::dump;
+use crate::file::{DiskStamp, File};
+use crate::keymap::Mappings;
 use crate::modes::Mode;
+use crate::register::Registers;
+use crate::search::Search;
 use crate::tabs::Tab;
+use crate::theme::Theme;
 use crate::tilde_expand::tilde_expand;
-use crate::util::HexCursor;
+
+pub enum Message {
+    Info(String),
+    Error(String),
+}
 
 pub struct App {
     pub tabs: Vec<Tab>,
     pub mode: Mode,
     pub command: String,
+    /// Shown in place of the command line until the next keypress
+    pub message: Option<Message>,
     pub size: Rect,
     pub tabs_index: usize,
     pub line_count: usize,
     pub options: AppOptions,
+    pub registers: Registers,
+    /// Register selected with `"x` for the next yank, delete or put
+    pub register: Option<char>,
+    /// First key of a multi-key command, e.g. `"` of `"a`
+    pub pending_key: Option<Key>,
+    pub last_search: Option<Search>,
+    /// Whether matches of `last_search` are highlighted, turned off by `:noh`
+    pub search_highlight: bool,
+    pub theme: Theme,
+    pub mappings: Mappings,
+    /// Keys produced by a mapping, handled before reading more input
+    pub typeahead: VecDeque<Key>,
 }
 
 impl App {
-    pub fn open(&mut self, filename: &str) -> io::Result<()> {
-        let expanded_path = tilde_expand(filename).unwrap_or_default();
-        let mut data: Vec<u8>;
-        match fs::File::open(expanded_path) {
-            Ok(mut f) => {
-                data = Vec::new();
-                f.read_to_end(&mut data)?;
-            }
-            Err(_e) => {
-                data = vec![];
-            }
+    /// The title screen, before any file is opened
+    pub fn new() -> App {
+        App {
+            tabs: vec![Tab::Title],
+            mode: Mode::Title,
+            command: String::new(),
+            message: None,
+            size: Rect::new(0, 0, 0, 0),
+            tabs_index: 0,
+            line_count: 0,
+            options: AppOptions::new(),
+            registers: Registers::new(),
+            register: None,
+            pending_key: None,
+            last_search: None,
+            search_highlight: false,
+            theme: Theme::default(),
+            mappings: Mappings::new(),
+            typeahead: VecDeque::new(),
         }
-        let file = File {
-            name: if let Some(s) = Path::new(filename).file_name() {
-                s.to_str().unwrap().to_string()
-            } else {
-                filename.to_string()
-            },
-            path: filename.to_string(),
-            cursor: HexCursor::new((0, 0)),
-            data,
-            scroll_y: 0x10,
-        };
+    }
+
+    pub fn open(&mut self, filename: &str, read_only: bool) -> io::Result<()> {
+        let mut file = File::open(filename, read_only)?;
+        file.cursor.set_cols(self.options.cols);
         if self.tabs.len() == 1 {
             if let Tab::Title = self.tabs[0] {
                 self.tabs.remove(0);
             }
         }
-        self.tabs.push(Tab::File(file));
+        self.tabs.push(Tab::File(Box::new(file)));
         Ok(())
     }
 
-    pub fn write<'a, T: Into<Option<&'a str>>>(&mut self, filename: T) -> io::Result<()> {
-        if let Tab::File(current_file) = &self.tabs[self.tabs_index] {
-            let mut f = fs::File::create(filename.into().unwrap_or(&current_file.path[..]))?;
-            f.write_all(&current_file.data[..])?;
-            f.sync_all()?;
+    /// Write the current buffer to `filename` or the path it was opened from,
+    /// returning the number of bytes written. Only writing to the buffer's own
+    /// path clears its modified flag.
+    pub fn write<'a, T: Into<Option<&'a str>>>(&mut self, filename: T) -> io::Result<usize> {
+        let mut written = 0;
+        if let Tab::File(current_file) = &mut self.tabs[self.tabs_index] {
+            let path = filename.into().unwrap_or(&current_file.path[..]);
+            let same_file = current_file.is_own_path(path);
+            let path = tilde_expand(path).unwrap_or_default();
+            // The buffer may still read unmodified bytes from the original file, which
+            // stays alive through the open handle after being replaced
+            let in_place = if self.options.in_place && same_file {
+                current_file.data.write_in_place(&path)?
+            } else {
+                None
+            };
+            written = match in_place {
+                Some(written) => written,
+                None => write_atomic(&current_file.data, &path, self.options.backup)?,
+            };
+            if same_file {
+                current_file.mark_saved();
+            }
+        }
+        Ok(written)
+    }
+
+    /// Switch to `theme`, along with the `:set color` it goes with
+    pub fn set_theme(&mut self, theme: Theme) {
+        if let Some(color) = theme.color {
+            self.options.color = color;
+        }
+        self.theme = theme;
+    }
+
+    /// Change the number of bytes per row of every open file
+    pub fn set_cols(&mut self, cols: usize) {
+        self.options.cols = cols;
+        for tab in &mut self.tabs {
+            if let Tab::File(file) = tab {
+                file.cursor.set_cols(cols);
+                file.scroll_y = (file.scroll_y / cols) * cols;
+            }
         }
-        Ok(())
+    }
+
+    /// Bytes per row that fit in a view `width` columns wide, a multiple of 8 or
+    /// of the group size
+    pub fn fit_cols(&self, width: usize) -> usize {
+        let step = usize::max(8, self.options.group);
+        // Borders, offset, the gap before the ASCII column and a column per byte
+        let row_width = |cols| 2 + 9 + dump::hex_width(cols, self.options.group) + 2 + cols;
+        let mut cols = step;
+        while row_width(cols + step) <= width {
+            cols += step;
+        }
+        cols
+    }
+
+    /// Warn about open files that another program changed since they were last
+    /// read or written. Each change is only reported once unless `recheck` is set.
+    pub fn check_time(&mut self, recheck: bool) {
+        let mut warning = None;
+        for tab in &mut self.tabs {
+            if let Tab::File(file) = tab {
+                if (recheck || !file.warned_changed) && file.changed_on_disk() {
+                    file.warned_changed = true;
+                    warning = Some(if DiskStamp::of(&file.path).is_none() {
+                        format!("E211: File \"{}\" no longer available", file.name)
+                    } else if file.data.original_changed() {
+                        // Changed in place, so bytes that were never read are gone
+                        format!(
+                            "W11: Warning: File \"{}\" was changed in place, the parts not loaded yet are lost, :e! to reload",
+                            file.name
+                        )
+                    } else {
+                        format!(
+                            "W11: Warning: File \"{}\" has changed since editing started, :e! to reload",
+                            file.name
+                        )
+                    });
+                }
+            }
+        }
+        if let Some(warning) = warning {
+            self.error(warning);
+        }
+    }
+
+    pub fn info<T: Into<String>>(&mut self, text: T) {
+        self.message = Some(Message::Info(text.into()));
+    }
+
+    pub fn error<T: Into<String>>(&mut self, text: T) {
+        self.message = Some(Message::Error(text.into()));
     }
 
     pub fn goto_tab(&mut self, idx: usize) {
@@ -85,8 +201,8 @@ impl App {
         }
     }
 
-    pub fn tab_titles(&mut self) -> Vec<&str> {
-        self.tabs.iter().map(|x| x.title()).collect::<Vec<&str>>()
+    pub fn tab_titles(&mut self) -> Vec<String> {
+        self.tabs.iter().map(|x| x.title()).collect::<Vec<String>>()
     }
 
     pub fn current_tab(&self) -> &Tab {
@@ -96,18 +212,96 @@ impl App {
 
 pub struct AppOptions {
     pub big_endian: bool,
+    /// Bytes shown per row
+    pub cols: usize,
+    /// Fit as many bytes per row as the terminal has room for
+    pub auto_cols: bool,
+    /// Bytes shown together without a space between them
+    pub group: usize,
     pub type_inspector: bool,
+    pub color: ColorScheme,
+    /// Keep the previous version of a file as `file~` when overwriting it
+    pub backup: bool,
+    /// Write only the modified bytes straight into the file when its length is
+    /// unchanged, instead of replacing it atomically
+    pub in_place: bool,
 }
 
 impl AppOptions {
     pub fn new() -> AppOptions {
         AppOptions {
             big_endian: false,
+            cols: 0x10,
+            auto_cols: false,
+            group: 1,
             type_inspector: true,
+            color: ColorScheme::Class,
+            backup: false,
+            in_place: false,
         }
     }
 }
 
+/// Write `data` to a temporary file next to `path` and rename it over `path`, so a
+/// crash mid-write never leaves a truncated file behind. The mode bits (and owner
+/// where allowed) of the old file are carried over.
+fn write_atomic(data: &Buffer, path: &str, backup: bool) -> io::Result<usize> {
+    // Write through symlinks instead of replacing them
+    let path = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    let metadata = fs::metadata(&path).ok();
+    let tmp_path = path_with_suffix(&path, ".nail-tmp");
+
+    let written = match write_new_file(data, &tmp_path, metadata.as_ref()) {
+        Ok(written) => written,
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+    };
+    if backup && metadata.is_some() {
+        // Linking keeps the old contents without copying them
+        let backup_path = path_with_suffix(&path, "~");
+        let _ = fs::remove_file(&backup_path);
+        if fs::hard_link(&path, &backup_path).is_err() {
+            fs::copy(&path, &backup_path)?;
+        }
+    }
+    fs::rename(&tmp_path, &path)?;
+
+    // Make the rename itself durable
+    #[cfg(unix)]
+    if let Some(dir) = path.parent().and_then(|dir| fs::File::open(dir).ok()) {
+        let _ = dir.sync_all();
+    }
+    Ok(written)
+}
+
+fn write_new_file(data: &Buffer, path: &Path, old: Option<&fs::Metadata>) -> io::Result<usize> {
+    let f = fs::File::create(path)?;
+    if let Some(old) = old {
+        f.set_permissions(old.permissions())?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{fchown, MetadataExt};
+            // Only root can give a file away, so keeping the owner is best effort
+            let _ = fchown(&f, Some(old.uid()), Some(old.gid()));
+        }
+    }
+    let mut writer = io::BufWriter::new(f);
+    let written = data.write_to(&mut writer)?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    Ok(written)
+}
+
+fn path_with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
 pub type Term = Terminal<
     TermionBackend<AlternateScreen<MouseTerminal<termion::raw::RawTerminal<std::io::Stdout>>>>,
 >;
@@ -0,0 +1,214 @@
+use crate::dump::DumpFormat;
+
+pub const USAGE: &str = "Usage: nail [options] [file ...]
+       nail --dump [-s offset] [-l len] [--cols N] [--format fmt] file
+       nail --revert dump.txt [-o out]
+       nail -s script.nail [file ...]
+
+Options:
+  -R              open the files read-only
+  +0xOFFSET       start at OFFSET, any other +CMD runs :CMD
+  -c CMD          run :CMD after the files are loaded, can be repeated
+  -s SCRIPT       run the commands in SCRIPT, one per line, without a terminal and
+                  exit, failing on the first error (with --dump: the offset to start at)
+  --be, --le      read multi-byte values as big/little endian
+  --cols N        show N bytes per row, up to 256
+  --dump          print the file as text to stdout instead of editing it
+  -l LEN          only dump LEN bytes
+  --format FMT    dump as nail (the editor's layout, default), xxd, plain (xxd -p)
+                  or c (xxd -i)
+  --revert DUMP   turn a hex dump in any of those formats except c back into bytes,
+                  written to stdout
+  -o OUT          write the bytes turned back from a dump to OUT instead
+  --version       print the version and exit
+  -h, --help      print this help and exit
+  --              treat every following argument as a file
+
+Files:
+  ~/.config/nail/nailrc      commands run on startup, one per line, unless a script
+                             is run with -s. $NAILRC names another file instead
+  .nailrc                    run after it if found in the current directory, once
+                             you agree to
+  ~/.config/nail/theme.toml  colors, see :colorscheme";
+
+const MAX_COLS: usize = 0x100;
+
+/// What nail was asked to do on the command line
+pub enum Action {
+    Edit(Args),
+    Dump(DumpArgs),
+    Revert {
+        dump: String,
+        output: Option<String>,
+    },
+    Help,
+    Version,
+}
+
+pub struct Args {
+    pub files: Vec<String>,
+    pub read_only: bool,
+    /// Command lines, including the leading `:`, to run once the files are loaded
+    pub commands: Vec<String>,
+    pub big_endian: Option<bool>,
+    pub cols: Option<usize>,
+    /// Commands to run instead of starting the editor
+    pub script: Option<String>,
+}
+
+pub struct DumpArgs {
+    pub file: String,
+    pub offset: usize,
+    pub length: Option<usize>,
+    pub cols: Option<usize>,
+    pub format: DumpFormat,
+}
+
+/// Parse the arguments following the program name
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Action, String> {
+    let mut parsed = Args {
+        files: Vec::new(),
+        read_only: false,
+        commands: Vec::new(),
+        big_endian: None,
+        cols: None,
+        script: None,
+    };
+    // `+CMD` runs before any `-c CMD`, like in vim
+    let mut start_commands = Vec::new();
+    let mut dump = false;
+    // `-s` is the start offset of a dump, otherwise a script
+    let mut s_arg = None;
+    let mut length = None;
+    let mut format = None;
+    let mut revert = None;
+    let mut output = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--" => {
+                parsed.files.extend(args);
+                break;
+            }
+            "-h" | "--help" => return Ok(Action::Help),
+            "--version" => return Ok(Action::Version),
+            "-R" => parsed.read_only = true,
+            "--be" => parsed.big_endian = Some(true),
+            "--le" => parsed.big_endian = Some(false),
+            "-c" => {
+                let command = args
+                    .next()
+                    .ok_or_else(|| String::from("option -c requires a command"))?;
+                parsed.commands.push(format!(":{}", command));
+            }
+            "--cols" => {
+                let cols = args
+                    .next()
+                    .ok_or_else(|| String::from("option --cols requires a number"))?;
+                parsed.cols = Some(parse_cols(&cols)?);
+            }
+            "--dump" => dump = true,
+            "--revert" => {
+                let dump = args
+                    .next()
+                    .ok_or_else(|| String::from("option --revert requires a file"))?;
+                revert = Some(dump);
+            }
+            "-o" => {
+                let out = args
+                    .next()
+                    .ok_or_else(|| String::from("option -o requires a file"))?;
+                output = Some(out);
+            }
+            "-s" => {
+                let arg = args
+                    .next()
+                    .ok_or_else(|| String::from("option -s requires an argument"))?;
+                s_arg = Some(arg);
+            }
+            "-l" => {
+                let arg = args
+                    .next()
+                    .ok_or_else(|| String::from("option -l requires a number"))?;
+                length = Some(parse_number("-l", arg)?);
+            }
+            "--format" => {
+                let name = args
+                    .next()
+                    .ok_or_else(|| String::from("option --format requires a format"))?;
+                let dump_format = DumpFormat::new(&name).ok_or_else(|| {
+                    format!(
+                        "unknown dump format {}, expected one of: {}",
+                        name,
+                        DumpFormat::NAMES.join(", ")
+                    )
+                })?;
+                format = Some(dump_format);
+            }
+            arg if arg.starts_with("--cols=") => {
+                parsed.cols = Some(parse_cols(&arg["--cols=".len()..])?);
+            }
+            arg if arg.starts_with('+') && arg.len() > 1 => {
+                start_commands.push(format!(":{}", &arg[1..]));
+            }
+            arg if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(format!("unknown option: {}", arg));
+            }
+            _ => parsed.files.push(arg),
+        }
+    }
+    if let Some(dump) = revert {
+        if !parsed.files.is_empty() {
+            return Err(String::from("--revert takes no other files, use -o"));
+        }
+        return Ok(Action::Revert { dump, output });
+    }
+    if output.is_some() {
+        return Err(String::from("option -o only works with --revert"));
+    }
+    if dump {
+        if parsed.files.len() != 1 {
+            return Err(String::from("--dump takes exactly one file"));
+        }
+        return Ok(Action::Dump(DumpArgs {
+            file: parsed.files.remove(0),
+            offset: match s_arg {
+                Some(offset) => parse_number("-s", offset)?,
+                None => 0,
+            },
+            length,
+            cols: parsed.cols,
+            format: format.unwrap_or(DumpFormat::Nail),
+        }));
+    }
+    if length.is_some() || format.is_some() {
+        return Err(String::from(
+            "options -l and --format only work with --dump",
+        ));
+    }
+    parsed.script = s_arg;
+
+    start_commands.append(&mut parsed.commands);
+    parsed.commands = start_commands;
+    Ok(Action::Edit(parsed))
+}
+
+/// Parse the value of `option` as hex with a `0x` prefix or decimal
+fn parse_number(option: &str, value: String) -> Result<usize, String> {
+    let number = match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    number.map_err(|_e| format!("invalid number for {}: {}", option, value))
+}
+
+/// Bytes per row, for both `--cols` and `:set cols`
+pub fn parse_cols(cols: &str) -> Result<usize, String> {
+    match cols.parse() {
+        Ok(cols) if cols > 0 && cols <= MAX_COLS => Ok(cols),
+        _ => Err(format!(
+            "invalid number of columns: {} (1 to {})",
+            cols, MAX_COLS
+        )),
+    }
+}
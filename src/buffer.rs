@@ -0,0 +1,782 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::ops::Range;
+use std::path::Path;
+use std::time::SystemTime;
+
+const PAGE_SIZE: usize = 0x10000;
+const MAX_PAGES: usize = 64;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Source {
+    Original,
+    Add,
+}
+
+/// A run of bytes taken from either the original file or the add buffer.
+/// Both are never modified in place, so a piece stays valid for the lifetime
+/// of the `Buffer` and can be kept around by the undo history.
+#[derive(Clone, Copy)]
+pub struct Piece {
+    pub source: Source,
+    pub start: usize,
+    pub len: usize,
+}
+
+pub fn pieces_len(pieces: &[Piece]) -> usize {
+    pieces.iter().map(|p| p.len).sum()
+}
+
+/// Sort `ranges` and join the ones that overlap or touch
+fn merge_ranges(mut ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => {
+                last.end = usize::max(last.end, range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// Size and modification time of the original file, to notice it being changed
+/// in place while parts of it still have to be read
+#[derive(PartialEq)]
+struct FileStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl FileStamp {
+    fn of(file: &fs::File) -> io::Result<FileStamp> {
+        let metadata = file.metadata()?;
+        Ok(FileStamp {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+/// Error reading a part of the original file that wasn't loaded before another
+/// program changed the file
+fn changed_error() -> io::Error {
+    io::Error::other("file changed on disk, the parts not loaded yet are lost (:e! reloads it)")
+}
+
+/// Bounded cache of pages read from the original file
+struct PageCache {
+    pages: HashMap<usize, Vec<u8>>,
+    order: VecDeque<usize>,
+}
+
+enum Original {
+    Memory(Vec<u8>),
+    File {
+        file: RefCell<fs::File>,
+        len: usize,
+        /// The file as it was when the cached pages were read
+        stamp: FileStamp,
+        cache: RefCell<PageCache>,
+        /// Bytes of the file from before it was overwritten in place, by offset
+        preserved: BTreeMap<usize, Vec<u8>>,
+    },
+}
+
+impl Original {
+    fn len(&self) -> usize {
+        match self {
+            Original::Memory(data) => data.len(),
+            Original::File { len, .. } => *len,
+        }
+    }
+
+    fn read(&self, offset: usize, buf: &mut [u8]) -> io::Result<()> {
+        let (file, stamp, cache, preserved) = match self {
+            Original::Memory(data) => {
+                buf.copy_from_slice(&data[offset..offset + buf.len()]);
+                return Ok(());
+            }
+            Original::File {
+                file,
+                stamp,
+                cache,
+                preserved,
+                ..
+            } => (file, stamp, cache, preserved),
+        };
+
+        let mut cache = cache.borrow_mut();
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let page_idx = pos / PAGE_SIZE;
+            if !cache.pages.contains_key(&page_idx) {
+                let mut page = vec![0; usize::min(PAGE_SIZE, self.len() - page_idx * PAGE_SIZE)];
+                let mut file = file.borrow_mut();
+                if FileStamp::of(&file)? != *stamp {
+                    return Err(changed_error());
+                }
+                file.seek(SeekFrom::Start((page_idx * PAGE_SIZE) as u64))?;
+                file.read_exact(&mut page)?;
+                if cache.order.len() >= MAX_PAGES {
+                    let evicted = cache.order.pop_front().unwrap();
+                    cache.pages.remove(&evicted);
+                }
+                cache.order.push_back(page_idx);
+                cache.pages.insert(page_idx, page);
+            }
+            let page = &cache.pages[&page_idx];
+            let page_offset = pos % PAGE_SIZE;
+            let count = usize::min(buf.len() - done, page.len() - page_offset);
+            buf[done..done + count].copy_from_slice(&page[page_offset..page_offset + count]);
+            done += count;
+        }
+
+        let end = offset + buf.len();
+        for (&start, old) in preserved.range(..end) {
+            let old_end = start + old.len();
+            if old_end > offset {
+                let from = usize::max(start, offset);
+                let to = usize::min(old_end, end);
+                buf[from - offset..to - offset].copy_from_slice(&old[from - start..to - start]);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the file was changed in place since it was opened or written
+    fn changed(&self) -> bool {
+        match self {
+            Original::Memory(_) => false,
+            Original::File { file, stamp, .. } => match FileStamp::of(&file.borrow()) {
+                Ok(now) => now != *stamp,
+                Err(_) => true,
+            },
+        }
+    }
+
+    fn preserved_ranges(&self) -> Vec<Range<usize>> {
+        match self {
+            Original::Memory(_) => vec![],
+            Original::File { preserved, .. } => preserved
+                .iter()
+                .map(|(&start, old)| start..start + old.len())
+                .collect(),
+        }
+    }
+
+    /// Copy the bytes in `range` that aren't preserved yet into memory, so they
+    /// can still be read after the file is overwritten
+    fn preserve(&mut self, range: Range<usize>) -> io::Result<()> {
+        let mut gaps = vec![];
+        let mut pos = range.start;
+        for old in self.preserved_ranges() {
+            if old.start >= range.end {
+                break;
+            }
+            if old.start > pos {
+                gaps.push(pos..old.start);
+            }
+            pos = usize::max(pos, old.end);
+        }
+        if pos < range.end {
+            gaps.push(pos..range.end);
+        }
+
+        for gap in gaps {
+            let mut old = vec![0; gap.len()];
+            self.read(gap.start, &mut old)?;
+            if let Original::File { preserved, .. } = self {
+                preserved.insert(gap.start, old);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Piece table over the file being edited. The original file is read lazily a
+/// page at a time and every edit is appended to the add buffer, so opening and
+/// editing huge files only costs memory proportional to what was viewed or typed.
+pub struct Buffer {
+    original: Original,
+    add: Vec<u8>,
+    pieces: Vec<Piece>,
+    /// Offset in the buffer each piece starts at, to find pieces by binary search
+    offsets: Vec<usize>,
+    len: usize,
+}
+
+impl Buffer {
+    pub fn new() -> Buffer {
+        Buffer::from_vec(Vec::new())
+    }
+
+    pub fn from_vec(data: Vec<u8>) -> Buffer {
+        Buffer::with_original(Original::Memory(data))
+    }
+
+    /// Open `path` without reading it; pages are loaded as they are accessed
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Buffer> {
+        let file = fs::File::open(path)?;
+        let stamp = FileStamp::of(&file)?;
+        Ok(Buffer::with_original(Original::File {
+            len: stamp.len as usize,
+            stamp,
+            file: RefCell::new(file),
+            cache: RefCell::new(PageCache {
+                pages: HashMap::new(),
+                order: VecDeque::new(),
+            }),
+            preserved: BTreeMap::new(),
+        }))
+    }
+
+    fn with_original(original: Original) -> Buffer {
+        let len = original.len();
+        let pieces = if len > 0 {
+            vec![Piece {
+                source: Source::Original,
+                start: 0,
+                len,
+            }]
+        } else {
+            vec![]
+        };
+        Buffer {
+            original,
+            add: Vec::new(),
+            offsets: vec![0; pieces.len()],
+            pieces,
+            len,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn pieces(&self) -> &[Piece] {
+        &self.pieces
+    }
+
+    pub fn get(&self, offset: usize) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        Ok(match self.read(offset, &mut byte)? {
+            1 => Some(byte[0]),
+            _ => None,
+        })
+    }
+
+    /// Read up to `len` bytes starting at `offset`
+    pub fn slice(&self, offset: usize, len: usize) -> io::Result<Vec<u8>> {
+        let len = usize::min(len, self.len.saturating_sub(offset));
+        let mut data = vec![0; len];
+        let read = self.read(offset, &mut data)?;
+        data.truncate(read);
+        Ok(data)
+    }
+
+    /// Pieces holding the bytes in `range`
+    pub fn pieces_in(&self, range: Range<usize>) -> Vec<Piece> {
+        let mut pieces = vec![];
+        let mut i = self.piece_at(range.start);
+        while i < self.pieces.len() && self.offsets[i] < range.end && !range.is_empty() {
+            let piece = self.pieces[i];
+            let skip = range.start.saturating_sub(self.offsets[i]);
+            let end = usize::min(piece.len, range.end - self.offsets[i]);
+            pieces.push(Piece {
+                source: piece.source,
+                start: piece.start + skip,
+                len: end - skip,
+            });
+            i += 1;
+        }
+        pieces
+    }
+
+    /// Index of the piece containing `offset`, or `pieces.len()` past the end
+    fn piece_at(&self, offset: usize) -> usize {
+        if offset >= self.len {
+            return self.pieces.len();
+        }
+        self.offsets.partition_point(|&start| start <= offset) - 1
+    }
+
+    /// Copy up to `buf.len()` bytes starting at `offset` into `buf`, returning the
+    /// number of bytes copied
+    pub fn read(&self, offset: usize, buf: &mut [u8]) -> io::Result<usize> {
+        let mut done = 0;
+        let mut i = self.piece_at(offset);
+        while done < buf.len() && i < self.pieces.len() {
+            let piece = &self.pieces[i];
+            let skip = offset + done - self.offsets[i];
+            let count = usize::min(buf.len() - done, piece.len - skip);
+            self.read_piece(piece, skip, &mut buf[done..done + count])?;
+            done += count;
+            i += 1;
+        }
+        Ok(done)
+    }
+
+    fn read_piece(&self, piece: &Piece, skip: usize, buf: &mut [u8]) -> io::Result<()> {
+        let start = piece.start + skip;
+        match piece.source {
+            Source::Original => self.original.read(start, buf),
+            Source::Add => {
+                buf.copy_from_slice(&self.add[start..start + buf.len()]);
+                Ok(())
+            }
+        }
+    }
+
+    /// Store `data` in the add buffer, returning the pieces that reference it
+    pub fn append_bytes(&mut self, data: &[u8]) -> Vec<Piece> {
+        if data.is_empty() {
+            return vec![];
+        }
+        let start = self.add.len();
+        self.add.extend_from_slice(data);
+        vec![Piece {
+            source: Source::Add,
+            start,
+            len: data.len(),
+        }]
+    }
+
+    /// Replace `len` bytes at `offset` with `new`, returning the pieces that were removed
+    pub fn replace(&mut self, offset: usize, len: usize, new: Vec<Piece>) -> Vec<Piece> {
+        let first = self.split_at(offset);
+        let last = self.split_at(offset + len);
+        self.len = self.len - len + pieces_len(&new);
+        let removed = self.pieces.splice(first..last, new).collect();
+        // The offsets after the splice are recomputed by `coalesce`
+        self.offsets.truncate(first);
+        self.coalesce();
+        removed
+    }
+
+    /// Split the piece containing `offset` so that a piece starts there, returning its index
+    fn split_at(&mut self, offset: usize) -> usize {
+        let i = self.piece_at(offset);
+        if i == self.pieces.len() || self.offsets[i] == offset {
+            return i;
+        }
+        let piece = self.pieces[i];
+        let skip = offset - self.offsets[i];
+        self.pieces[i].len = skip;
+        self.pieces.insert(
+            i + 1,
+            Piece {
+                source: piece.source,
+                start: piece.start + skip,
+                len: piece.len - skip,
+            },
+        );
+        self.offsets.insert(i + 1, offset);
+        i + 1
+    }
+
+    /// Merge neighbouring pieces that continue each other, drop empty ones and
+    /// recompute where each piece starts
+    fn coalesce(&mut self) {
+        let mut pieces: Vec<Piece> = Vec::with_capacity(self.pieces.len());
+        for piece in self.pieces.drain(..).filter(|p| p.len > 0) {
+            match pieces.last_mut() {
+                Some(last)
+                    if last.source == piece.source && last.start + last.len == piece.start =>
+                {
+                    last.len += piece.len;
+                }
+                _ => pieces.push(piece),
+            }
+        }
+        self.pieces = pieces;
+        self.offsets.clear();
+        let mut offset = 0;
+        for piece in &self.pieces {
+            self.offsets.push(offset);
+            offset += piece.len;
+        }
+    }
+
+    /// Whether the file the buffer reads from was changed in place by another
+    /// program, so the parts of it not loaded yet can't be read anymore
+    pub fn original_changed(&self) -> bool {
+        self.original.changed()
+    }
+
+    /// Ranges that no longer match the file on disk at the same offset, in order
+    fn dirty_ranges(&self) -> Vec<Range<usize>> {
+        let mut ranges = self.original.preserved_ranges();
+        let mut offset = 0;
+        for piece in &self.pieces {
+            if piece.source != Source::Original || piece.start != offset {
+                ranges.push(offset..offset + piece.len);
+            }
+            offset += piece.len;
+        }
+        merge_ranges(ranges)
+    }
+
+    /// Ranges holding bytes that weren't anywhere in `saved`, an earlier copy of
+    /// `pieces()`, in order. Bytes that only moved because of an insertion or
+    /// deletion before them don't count. Bytes of `saved` that are gone are an
+    /// empty range where they used to be, unless that touches another change.
+    pub fn changes_since(&self, saved: &[Piece]) -> Vec<Range<usize>> {
+        let known = |source| {
+            merge_ranges(
+                saved
+                    .iter()
+                    .filter(|p| p.source == source)
+                    .map(|p| p.start..p.start + p.len)
+                    .collect(),
+            )
+        };
+        let (original, add) = (known(Source::Original), known(Source::Add));
+
+        let mut changes = Vec::new();
+        let mut offset = 0;
+        for piece in &self.pieces {
+            let known = match piece.source {
+                Source::Original => &original,
+                Source::Add => &add,
+            };
+            let end = piece.start + piece.len;
+            let mut pos = piece.start;
+            let first = known.partition_point(|r| r.end <= pos);
+            for range in known[first..].iter().take_while(|r| r.start < end) {
+                if range.start > pos {
+                    changes.push(offset + pos - piece.start..offset + range.start - piece.start);
+                }
+                pos = usize::max(pos, range.end);
+            }
+            if pos < end {
+                changes.push(offset + pos - piece.start..offset + piece.len);
+            }
+            offset += piece.len;
+        }
+        let mut changes = merge_ranges(changes);
+
+        let touches_change = |point: usize| {
+            let idx = changes.partition_point(|c| c.end < point);
+            changes.get(idx).is_some_and(|c| c.start <= point)
+        };
+        let mut deletions = self
+            .deletions_since(saved)
+            .into_iter()
+            .filter(|&point| !touches_change(point))
+            .collect::<Vec<usize>>();
+        deletions.dedup();
+        changes.extend(deletions.into_iter().map(|point| point..point));
+        changes.sort_by_key(|c| c.start);
+        changes
+    }
+
+    /// Offsets of the bytes that now follow bytes of `saved` that are gone, or the
+    /// end of the buffer if nothing does
+    fn deletions_since(&self, saved: &[Piece]) -> Vec<usize> {
+        // Where each byte of the sources is now, without overlaps so both the
+        // starts and the ends are in order
+        let mut located = Vec::with_capacity(self.pieces.len());
+        let mut order = (0..self.pieces.len()).collect::<Vec<usize>>();
+        order.sort_by_key(|&i| (self.pieces[i].source == Source::Add, self.pieces[i].start));
+        let mut last: Option<Piece> = None;
+        for i in order {
+            let mut piece = self.pieces[i];
+            let mut offset = self.offsets[i];
+            if let Some(last) = last.filter(|last| last.source == piece.source) {
+                let skip = usize::min(
+                    piece.len,
+                    (last.start + last.len).saturating_sub(piece.start),
+                );
+                piece.start += skip;
+                piece.len -= skip;
+                offset += skip;
+            }
+            if piece.len > 0 {
+                located.push((piece, offset));
+                last = Some(piece);
+            }
+        }
+
+        let mut deletions = Vec::new();
+        let mut deleted = false;
+        for piece in saved {
+            let key = |source: Source, pos: usize| (source == Source::Add, pos);
+            let end = piece.start + piece.len;
+            let mut pos = piece.start;
+            let first = located
+                .partition_point(|(p, _)| key(p.source, p.start + p.len) <= key(piece.source, pos));
+            for (now, offset) in located[first..]
+                .iter()
+                .take_while(|(p, _)| key(p.source, p.start) < key(piece.source, end))
+            {
+                if now.start > pos {
+                    deleted = true;
+                }
+                let from = usize::max(pos, now.start);
+                if deleted {
+                    deletions.push(offset + from - now.start);
+                    deleted = false;
+                }
+                pos = usize::min(end, now.start + now.len);
+            }
+            if pos < end {
+                deleted = true;
+            }
+        }
+        if deleted {
+            deletions.push(self.len);
+        }
+        deletions.sort_unstable();
+        deletions
+    }
+
+    /// Overwrite only the modified ranges of the file the buffer was opened from,
+    /// returning the number of bytes written. This isn't atomic, but saving a few
+    /// edits to a huge file is instant. Returns `None` if it can't be done because
+    /// the length changed or `path` is no longer that file.
+    pub fn write_in_place<P: AsRef<Path>>(&mut self, path: P) -> io::Result<Option<usize>> {
+        match &self.original {
+            Original::File { file, len, .. }
+                if *len == self.len && same_file(&file.borrow(), path.as_ref()) => {}
+            _ => return Ok(None),
+        }
+
+        let ranges = self.dirty_ranges();
+        // Undo history and moved pieces may still refer to the bytes being overwritten
+        for range in &ranges {
+            self.original.preserve(range.clone())?;
+        }
+        let mut file = fs::OpenOptions::new().write(true).open(path)?;
+        let mut written = 0;
+        for range in ranges {
+            file.seek(SeekFrom::Start(range.start as u64))?;
+            written += self.write_range_to(range, &mut file)?;
+        }
+        file.sync_all()?;
+        // What's on disk now is what the buffer and preserved bytes say it is
+        if let Original::File { file, stamp, .. } = &mut self.original {
+            *stamp = FileStamp::of(&file.borrow())?;
+        }
+        Ok(Some(written))
+    }
+
+    /// Stream the whole buffer into `writer`, returning the number of bytes written
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<usize> {
+        self.write_range_to(0..self.len, writer)
+    }
+
+    /// Stream the bytes in `range` into `writer`, returning the number of bytes written
+    pub fn write_range_to<W: Write>(
+        &self,
+        range: Range<usize>,
+        writer: &mut W,
+    ) -> io::Result<usize> {
+        let mut chunk = vec![0; PAGE_SIZE];
+        let mut pos = range.start;
+        while pos < range.end {
+            let count = usize::min(PAGE_SIZE, range.end - pos);
+            let read = self.read(pos, &mut chunk[..count])?;
+            if read == 0 {
+                break;
+            }
+            writer.write_all(&chunk[..read])?;
+            pos += read;
+        }
+        Ok(pos - range.start)
+    }
+}
+
+#[cfg(unix)]
+fn same_file(file: &fs::File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_file(_file: &fs::File, _path: &Path) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_bytes(rng: &mut StdRng, max: usize) -> Vec<u8> {
+        let len = rng.gen_range(0..=max);
+        (0..len).map(|_| rng.gen()).collect()
+    }
+
+    /// Replace `len` bytes at `offset` in both the buffer and the model
+    fn splice(data: &mut Buffer, model: &mut Vec<u8>, offset: usize, len: usize, new: &[u8]) {
+        let pieces = data.append_bytes(new);
+        data.replace(offset, len, pieces);
+        model.splice(offset..offset + len, new.iter().copied());
+    }
+
+    fn check(data: &Buffer, model: &[u8], rng: &mut StdRng) {
+        assert_eq!(data.len(), model.len());
+        assert_eq!(data.slice(0, usize::MAX).unwrap(), model);
+        let offset = rng.gen_range(0..=model.len());
+        let len = rng.gen_range(0..64);
+        let end = usize::min(offset + len, model.len());
+        assert_eq!(data.slice(offset, len).unwrap(), &model[offset..end]);
+        assert_eq!(data.get(offset).unwrap(), model.get(offset).copied());
+        assert_eq!(pieces_len(data.pieces()), model.len());
+    }
+
+    #[test]
+    fn random_splices_match_model() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut model = random_bytes(&mut rng, 1000);
+        let mut data = Buffer::from_vec(model.clone());
+        for _ in 0..2000 {
+            let offset = rng.gen_range(0..=model.len());
+            let len = rng.gen_range(0..=usize::min(32, model.len() - offset));
+            let new = random_bytes(&mut rng, 32);
+            splice(&mut data, &mut model, offset, len, &new);
+            check(&data, &model, &mut rng);
+        }
+    }
+
+    #[test]
+    fn changes_since_matches_model() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut model = random_bytes(&mut rng, 500);
+        let mut data = Buffer::from_vec(model.clone());
+        // Index in `saved` of each byte of the model, None for ones typed since
+        let mut origin = (0..model.len()).map(Some).collect::<Vec<Option<usize>>>();
+        let mut saved = data.pieces().to_vec();
+        for step in 0..1000 {
+            if step % 100 == 0 {
+                saved = data.pieces().to_vec();
+                origin = (0..model.len()).map(Some).collect();
+            }
+            let offset = rng.gen_range(0..=model.len());
+            let len = rng.gen_range(0..=usize::min(16, model.len() - offset));
+            let new = match rng.gen_bool(0.3) {
+                true => vec![],
+                false => random_bytes(&mut rng, 16),
+            };
+            origin.splice(offset..offset + len, new.iter().map(|_| None));
+            splice(&mut data, &mut model, offset, len, &new);
+
+            let mut expected: Vec<Range<usize>> = Vec::new();
+            for (i, _) in origin.iter().enumerate().filter(|(_, o)| o.is_none()) {
+                match expected.last_mut() {
+                    Some(last) if last.end == i => last.end += 1,
+                    _ => expected.push(i..i + 1),
+                }
+            }
+            // A deletion is marked on the saved byte after it, if it isn't next to a change
+            let mut now = vec![None; pieces_len(&saved)];
+            for (i, o) in origin.iter().enumerate() {
+                if let Some(o) = o {
+                    now[*o] = Some(i);
+                }
+            }
+            let mut deletions = vec![];
+            let mut deleted = false;
+            for i in now {
+                match i {
+                    None => deleted = true,
+                    Some(i) if deleted => {
+                        deletions.push(i);
+                        deleted = false;
+                    }
+                    Some(_) => {}
+                }
+            }
+            if deleted {
+                deletions.push(model.len());
+            }
+            deletions.retain(|&d| !expected.iter().any(|c| c.start <= d && d <= c.end));
+            deletions.dedup();
+            expected.extend(deletions.into_iter().map(|d| d..d));
+            expected.sort_by_key(|c| c.start);
+            assert_eq!(data.changes_since(&saved), expected);
+        }
+    }
+
+    #[test]
+    fn changes_since_marks_a_deletion() {
+        let mut model = vec![0; 16];
+        let mut data = Buffer::from_vec(model.clone());
+        let saved = data.pieces().to_vec();
+        splice(&mut data, &mut model, 2, 3, &[]);
+        assert_eq!(data.changes_since(&saved), vec![2..2]);
+        splice(&mut data, &mut model, 11, 2, &[]);
+        assert_eq!(data.changes_since(&saved), vec![2..2, 11..11]);
+        splice(&mut data, &mut model, 0, 1, &[]);
+        assert_eq!(data.changes_since(&saved), vec![0..0, 1..1, 10..10]);
+        splice(&mut data, &mut model, 1, 0, &[1]);
+        assert_eq!(data.changes_since(&saved), vec![0..0, 1..2, 11..11]);
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("nail-test-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn write_in_place_preserves_overwritten_bytes() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let path = temp_path("write-in-place");
+        let original: Vec<u8> = (0..3 * PAGE_SIZE + 123).map(|_| rng.gen()).collect();
+        fs::write(&path, &original).unwrap();
+
+        let mut data = Buffer::open(&path).unwrap();
+        let mut model = original.clone();
+        // Pieces kept from before each save, as the undo history would
+        let mut snapshots = vec![(data.pieces().to_vec(), model.clone())];
+        for _ in 0..5 {
+            for _ in 0..20 {
+                let offset = rng.gen_range(0..model.len() - 64);
+                let new = random_bytes(&mut rng, 64);
+                splice(&mut data, &mut model, offset, new.len(), &new);
+            }
+            assert!(data.write_in_place(&path).unwrap().is_some());
+            assert_eq!(fs::read(&path).unwrap(), model);
+            check(&data, &model, &mut rng);
+            snapshots.push((data.pieces().to_vec(), model.clone()));
+        }
+
+        for (pieces, expected) in snapshots {
+            let len = data.len();
+            data.replace(0, len, pieces);
+            assert_eq!(data.slice(0, usize::MAX).unwrap(), expected);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reading_a_file_changed_in_place_fails() {
+        let path = temp_path("changed-in-place");
+        fs::write(&path, vec![0; 2 * PAGE_SIZE]).unwrap();
+        let data = Buffer::open(&path).unwrap();
+        assert_eq!(data.get(0).unwrap(), Some(0));
+
+        fs::write(&path, vec![1; 3 * PAGE_SIZE]).unwrap();
+        assert!(data.original_changed());
+        // The first page was loaded before the change, the second one wasn't
+        assert_eq!(data.get(0).unwrap(), Some(0));
+        assert!(data.get(PAGE_SIZE).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
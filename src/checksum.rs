@@ -0,0 +1,85 @@
+use std::io;
+use std::io::Write;
+
+/// Running checksum over a stream of bytes. Implements `Write` so a range of
+/// the buffer can be streamed straight into it.
+pub enum Checksum {
+    Sum8(u8),
+    Sum16(u16),
+    Xor8(u8),
+    Crc32(u32),
+    Adler32(u32, u32),
+}
+
+impl Checksum {
+    pub const NAMES: &'static [&'static str] = &["sum8", "sum16", "xor8", "crc32", "adler32"];
+
+    pub fn new(name: &str) -> Option<Checksum> {
+        match name {
+            "sum8" => Some(Checksum::Sum8(0)),
+            "sum16" => Some(Checksum::Sum16(0)),
+            "xor8" => Some(Checksum::Xor8(0)),
+            "crc32" => Some(Checksum::Crc32(!0)),
+            "adler32" => Some(Checksum::Adler32(1, 0)),
+            _ => None,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Checksum::Sum8(sum) => {
+                for byte in data {
+                    *sum = sum.wrapping_add(*byte);
+                }
+            }
+            Checksum::Sum16(sum) => {
+                for byte in data {
+                    *sum = sum.wrapping_add(*byte as u16);
+                }
+            }
+            Checksum::Xor8(sum) => {
+                for byte in data {
+                    *sum ^= *byte;
+                }
+            }
+            Checksum::Crc32(crc) => {
+                for byte in data {
+                    *crc ^= *byte as u32;
+                    for _ in 0..8 {
+                        *crc = if *crc & 1 != 0 {
+                            (*crc >> 1) ^ 0xEDB8_8320
+                        } else {
+                            *crc >> 1
+                        };
+                    }
+                }
+            }
+            Checksum::Adler32(a, b) => {
+                for byte in data {
+                    *a = (*a + *byte as u32) % 65521;
+                    *b = (*b + *a) % 65521;
+                }
+            }
+        }
+    }
+
+    pub fn finish(&self) -> String {
+        match self {
+            Checksum::Sum8(sum) | Checksum::Xor8(sum) => format!("0x{:02X}", sum),
+            Checksum::Sum16(sum) => format!("0x{:04X}", sum),
+            Checksum::Crc32(crc) => format!("0x{:08X}", !crc),
+            Checksum::Adler32(a, b) => format!("0x{:08X}", (b << 16) | a),
+        }
+    }
+}
+
+impl Write for Checksum {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
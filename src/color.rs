@@ -0,0 +1,45 @@
+use tui::style::{Color, Style};
+
+use crate::theme::Theme;
+
+/// How bytes are colored in the hex and ASCII columns
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColorScheme {
+    None,
+    /// A color per kind of byte, like hexyl
+    Class,
+    /// A color running from blue for 0x00 to red for 0xFF
+    Gradient,
+}
+
+impl ColorScheme {
+    pub const NAMES: &'static [&'static str] = &["none", "class", "gradient"];
+
+    pub fn new(name: &str) -> Option<ColorScheme> {
+        match name {
+            "none" => Some(ColorScheme::None),
+            "class" => Some(ColorScheme::Class),
+            "gradient" => Some(ColorScheme::Gradient),
+            _ => None,
+        }
+    }
+
+    pub fn style(self, byte: u8, theme: &Theme) -> Style {
+        match self {
+            ColorScheme::None => Style::default(),
+            ColorScheme::Class => class_style(byte, theme),
+            ColorScheme::Gradient => Style::default().fg(Color::Rgb(byte, 0x60, 0xFF - byte)),
+        }
+    }
+}
+
+fn class_style(byte: u8, theme: &Theme) -> Style {
+    match byte {
+        0x00 => theme.byte_null,
+        0xFF => theme.byte_ff,
+        b'\t' | b'\n' | 0x0B | 0x0C | b'\r' | b' ' => theme.byte_whitespace,
+        0x21..=0x7E => theme.byte_printable,
+        0x01..=0x1F | 0x7F => theme.byte_control,
+        _ => theme.byte_high,
+    }
+}
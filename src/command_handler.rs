@@ -1,57 +1,491 @@
-use crate::app::{App, Term};
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::process::Command;
+
+use termion::event::Key;
+
+use crate::app::App;
+use crate::args;
+use crate::buffer::Piece;
+use crate::checksum::Checksum;
+use crate::color::ColorScheme;
+use crate::dump;
+use crate::file::File as NailFile;
+use crate::keymap;
 use crate::modes::Mode;
+use crate::search::{self, literal_bytes, parse_search_query, Query, Search};
 use crate::tabs::Tab;
+use crate::theme::{Theme, THEME_PATH};
+use crate::tilde_expand::tilde_expand;
+use crate::util::parse_hex_bytes;
+
+/// Outcome of a command, errors being the message shown in the status line
+pub type CommandResult = Result<(), String>;
+
+/// Describe an io error the way vim does, e.g. "permission denied"
+pub fn describe_io_error(e: &io::Error) -> String {
+    let text = e.to_string();
+    let text = match text.find(" (os error") {
+        Some(idx) => &text[..idx],
+        None => &text[..],
+    };
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_lowercase().chain(chars).collect(),
+        None => text.to_string(),
+    }
+}
 
-fn handle_set(app: &mut App, option: &str) {
+fn handle_set(app: &mut App, option: &str) -> CommandResult {
+    let invalid = || format!("E474: Invalid argument: {}", option);
+    if let Some(cols) = option.strip_prefix("cols=") {
+        if cols == "auto" {
+            app.options.auto_cols = true;
+        } else {
+            let cols = args::parse_cols(cols).map_err(|_e| invalid())?;
+            app.options.auto_cols = false;
+            app.set_cols(cols);
+        }
+        return Ok(());
+    }
+    if let Some(group) = option.strip_prefix("group=") {
+        app.options.group = match group {
+            "1" | "2" | "4" | "8" => group.parse().map_err(|_e| invalid())?,
+            _ => return Err(invalid()),
+        };
+        return Ok(());
+    }
+    if let Some(color) = option.strip_prefix("color=") {
+        app.options.color = ColorScheme::new(color).ok_or_else(invalid)?;
+        return Ok(());
+    }
     match option {
         "be" | "bigendian" => app.options.big_endian = true,
         "le" | "littlendian" => app.options.big_endian = false,
-        _ => {}
+        "readonly" | "ro" | "noreadonly" | "noro" => {
+            if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+                current_file.read_only = !option.starts_with("no");
+            }
+        }
+        "backup" | "bk" => app.options.backup = true,
+        "nobackup" | "nobk" => app.options.backup = false,
+        "inplace" => app.options.in_place = true,
+        "noinplace" => app.options.in_place = false,
+        _ => return Err(format!("E518: Unknown option: {}", option)),
+    }
+    Ok(())
+}
+
+/// `:map` lists the mappings, `:map x` shows the one for `x` and `:map x keys`
+/// maps `x` to `keys`
+fn handle_map(app: &mut App, args: &str) -> CommandResult {
+    let (lhs, rhs) = match args.find(char::is_whitespace) {
+        Some(idx) => (&args[..idx], args[idx..].trim_start()),
+        None => (args, ""),
+    };
+    if lhs.is_empty() {
+        match app.mappings.list() {
+            list if list.is_empty() => app.info("No mapping found"),
+            list => app.info(list),
+        }
+        return Ok(());
+    }
+    let key = map_key(lhs)?;
+    if rhs.is_empty() {
+        let keys = app.mappings.get(key).ok_or("No mapping found")?;
+        let message = format!("{} {}", lhs, keymap::keys_name(keys));
+        app.info(message);
+    } else {
+        app.mappings.insert(key, keymap::parse_keys(rhs));
+    }
+    Ok(())
+}
+
+/// The key a mapping is for, only single keys can be mapped
+fn map_key(text: &str) -> Result<Key, String> {
+    match keymap::parse_keys(text)[..] {
+        [key] => Ok(key),
+        _ => Err(format!("E474: Invalid argument: {}", text)),
+    }
+}
+
+type Address<'a> = Result<Option<(usize, &'a str)>, String>;
+
+/// Parse a single address: `0x[hex]`, `.` (cursor), `$` (last byte) or a visual mark
+fn parse_address<'a>(file: &NailFile, text: &'a str) -> Address<'a> {
+    let mark_not_set = || String::from("E20: Mark not set");
+    let address = if let Some(rest) = text.strip_prefix('.') {
+        (file.cursor.loc(), rest)
+    } else if let Some(rest) = text.strip_prefix('$') {
+        (file.data.len().saturating_sub(1), rest)
+    } else if let Some(rest) = text.strip_prefix("'<") {
+        let selection = file.last_selection.as_ref().ok_or_else(mark_not_set)?;
+        (selection.start, rest)
+    } else if let Some(rest) = text.strip_prefix("'>") {
+        let selection = file.last_selection.as_ref().ok_or_else(mark_not_set)?;
+        (selection.end.saturating_sub(1), rest)
+    } else if let Some(rest) = text.strip_prefix("0x") {
+        let end = rest
+            .find(|c: char| !c.is_ascii_hexdigit())
+            .unwrap_or(rest.len());
+        let address = usize::from_str_radix(&rest[..end], 16)
+            .map_err(|_e| format!("E16: Invalid range: 0x{}", &rest[..end]))?;
+        (address, &rest[end..])
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(address))
+}
+
+type ParsedRange<'a> = Result<(Option<Range<usize>>, &'a str), String>;
+
+/// Split a leading byte range (`%`, `'<,'>` or `ADDR[,ADDR]`, inclusive) off a command
+fn parse_range<'a>(file: &NailFile, text: &'a str) -> ParsedRange<'a> {
+    if let Some(rest) = text.strip_prefix('%') {
+        return Ok((Some(0..file.data.len()), rest));
+    }
+    let (start, rest) = match parse_address(file, text)? {
+        Some(address) => address,
+        None => return Ok((None, text)),
+    };
+    let (last, rest) = match rest.strip_prefix(',') {
+        Some(rest) => parse_address(file, rest)?.ok_or("E14: Invalid address")?,
+        None => (start, rest),
+    };
+    if last < start {
+        return Err(String::from("E493: Backwards range given"));
+    }
+    let end = last.checked_add(1).ok_or("E16: Invalid range")?;
+    Ok((Some(start..end), rest))
+}
+
+/// Names of the commands `handle_range_command` runs, besides `s/`
+const RANGE_COMMANDS: &[&str] = &["d", "delete", "y", "yank", "fill", "export", "checksum"];
+
+/// Commands operating on a byte range. Without a range, `d`/`y`/`fill` use the
+/// byte under the cursor and `export`/`checksum` use the whole buffer. `d` and `y`
+/// take an optional register name like vim's `:d x`.
+/// Returns None if `command` isn't a range command.
+fn handle_range_command(
+    app: &mut App,
+    range: Option<Range<usize>>,
+    command: &str,
+) -> Option<CommandResult> {
+    let command = command.trim();
+    let (name, arg) = match command.find(' ') {
+        Some(idx) => (&command[..idx], command[idx..].trim()),
+        None => (command, ""),
+    };
+    let substitute = command.strip_prefix("s/");
+    if substitute.is_none() && !RANGE_COMMANDS.contains(&name) {
+        return None;
+    }
+    // Unlike a goto, a range naming bytes past the end is a mistake
+    if let (Some(range), Tab::File(current_file)) = (&range, &app.tabs[app.tabs_index]) {
+        if range.end > current_file.data.len() {
+            return Some(Err(String::from("E16: Invalid range")));
+        }
+    }
+    if let Some(args) = substitute {
+        return Some(handle_substitute(app, range, args));
     }
+    let selected_register = app.register;
+    let current_file = match &mut app.tabs[app.tabs_index] {
+        Tab::File(current_file) => current_file,
+        _ => return None,
+    };
+    if let "d" | "delete" | "fill" = name {
+        if let Err(e) = current_file.check_modifiable() {
+            return Some(Err(e));
+        }
+    }
+    let loc = current_file.cursor.loc();
+    let filesize = current_file.data.len();
+    let cursor_range = usize::min(loc, filesize)..usize::min(loc + 1, filesize);
+    match name {
+        "d" | "delete" => {
+            app.register = None;
+            let range = range.unwrap_or(cursor_range);
+            let register = arg.chars().next().or(selected_register);
+            let data = match current_file.read_range(range.clone()) {
+                Ok(data) => data,
+                Err(e) => return Some(Err(e)),
+            };
+            app.registers.delete(register, data);
+            current_file.delete_range(range);
+        }
+        "y" | "yank" => {
+            app.register = None;
+            let range = range.unwrap_or(cursor_range);
+            let register = arg.chars().next().or(selected_register);
+            let data = match current_file.read_range(range) {
+                Ok(data) => data,
+                Err(e) => return Some(Err(e)),
+            };
+            app.registers.yank(register, data);
+        }
+        "fill" => {
+            let range = range.unwrap_or(cursor_range);
+            let pattern = match parse_hex_bytes(arg) {
+                Some(pattern) => pattern,
+                None => return Some(Err(format!("E474: Invalid argument: {}", arg))),
+            };
+            let bytes = pattern
+                .iter()
+                .cloned()
+                .cycle()
+                .take(range.len())
+                .collect::<Vec<u8>>();
+            current_file.splice(range.start, range.len(), &bytes);
+            current_file.history.commit();
+        }
+        "export" => {
+            if arg.is_empty() {
+                return Some(Err(String::from("E32: No file name")));
+            }
+            let range = range.unwrap_or(0..filesize);
+            let path = tilde_expand(arg).unwrap_or_default();
+            let written = fs::File::create(path).and_then(|f| {
+                current_file
+                    .data
+                    .write_range_to(range, &mut io::BufWriter::new(f))
+            });
+            match written {
+                Ok(written) => app.info(format!("\"{}\" {}B written", arg, written)),
+                Err(e) => {
+                    return Some(Err(format!(
+                        "E212: Can't open file for writing: {}",
+                        describe_io_error(&e)
+                    )))
+                }
+            }
+        }
+        "checksum" => {
+            let range = range.unwrap_or(0..filesize);
+            let algorithm = if arg.is_empty() { "crc32" } else { arg };
+            let mut checksum = match Checksum::new(algorithm) {
+                Some(checksum) => checksum,
+                None => {
+                    return Some(Err(format!(
+                        "E474: Unknown checksum {}, expected one of: {}",
+                        algorithm,
+                        Checksum::NAMES.join(", ")
+                    )))
+                }
+            };
+            let start = range.start;
+            let end = range.end;
+            if let Err(e) = current_file.data.write_range_to(range, &mut checksum) {
+                return Some(Err(describe_io_error(&e)));
+            }
+            app.info(format!(
+                "{} of 0x{:X}-0x{:X}: {}",
+                algorithm,
+                start,
+                end.saturating_sub(1),
+                checksum.finish()
+            ));
+        }
+        _ => return None,
+    }
+    Some(Ok(()))
 }
 
-#[allow(unused_variables, unused_assignments)]
-pub fn handle_command(app: &mut App, terminal: &mut Term) {
-    // Example usage: "q!" will force quit
-    let mut force_command = false;
+/// Split `pattern/replacement/flags`, ignoring slashes in quotes or escaped with `\`
+fn split_substitute(args: &str) -> Option<(&str, &str, &str)> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (i, c) in args.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            '/' if !in_quotes && parts.len() < 2 => {
+                parts.push(&args[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&args[start..]);
+    match parts[..] {
+        [pattern, replacement] => Some((pattern, replacement, "")),
+        [pattern, replacement, flags] => Some((pattern, replacement, flags)),
+        _ => None,
+    }
+}
+
+/// `:[range]s/pattern/replacement/[flags]`, where the pattern is anything `/` accepts
+/// and the replacement is hex bytes, a string or a typed value. Without a range the
+/// current row is used. Flags: `g` replaces every match instead of the first one,
+/// `r` allows replacements of a different length, shifting the rest of the file.
+fn handle_substitute(app: &mut App, range: Option<Range<usize>>, args: &str) -> CommandResult {
+    if let Tab::File(current_file) = &app.tabs[app.tabs_index] {
+        current_file.check_modifiable()?;
+    }
+    let (pattern, replacement, flags) = split_substitute(args)
+        .ok_or_else(|| String::from("E471: Usage: s/pattern/replacement/[flags]"))?;
+    if let Some(flag) = flags.chars().find(|c| *c != 'g' && *c != 'r') {
+        return Err(format!("E488: Trailing characters: {}", flag));
+    }
+    let query = parse_search_query(pattern, app.options.big_endian)
+        .map_err(|e| format!("E383: Invalid search string: {}", e))?;
+    let replacement = if replacement.is_empty() {
+        vec![]
+    } else {
+        match parse_search_query(replacement, app.options.big_endian) {
+            // A tolerance only makes sense when searching
+            Ok(Query::Float { .. }) if replacement.contains('~') => None,
+            Ok(query) => literal_bytes(&query),
+            Err(_) => None,
+        }
+        .ok_or_else(|| format!("E474: Invalid replacement: {}", replacement))?
+    };
+    if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+        let cols = current_file.cursor.cols;
+        let row = (current_file.cursor.loc() / cols) * cols;
+        let range = range.unwrap_or(row..usize::min(row + cols, current_file.data.len()));
+        let result = search::substitute(
+            current_file,
+            &query,
+            &replacement,
+            range,
+            flags.contains('g'),
+            flags.contains('r'),
+        );
+        match result? {
+            0 => return Err(format!("E486: Pattern not found: {}", pattern)),
+            1 => app.info("1 substitution"),
+            count => app.info(format!("{} substitutions", count)),
+        }
+    }
+    Ok(())
+}
+
+/// Run the command line in `app.command`
+pub fn handle_command(app: &mut App) -> CommandResult {
     let command = app.command.clone();
     let mut command_chars = command.chars();
     app.command = String::new();
+    if let (Some(text), Tab::File(current_file)) =
+        (command.strip_prefix(':'), &app.tabs[app.tabs_index])
+    {
+        let (range, rest) = parse_range(current_file, text)?;
+        if let Some(result) = handle_range_command(app, range, rest) {
+            return result;
+        }
+    }
     if let Some(data) = command.strip_prefix(":0x") {
-        match i64::from_str_radix(data, 16) {
-            Ok(x) => {
-                let mut goto_address: usize = x as usize;
-                if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
-                    let filesize = current_file.data.len();
-                    if goto_address >= filesize {
-                        if filesize > 0 {
-                            goto_address = filesize - 1;
-                        } else {
-                            goto_address = 0;
-                        }
-                    }
-                    current_file.cursor.goto(goto_address as usize);
+        let mut goto_address = usize::from_str_radix(data.trim(), 16)
+            .map_err(|_e| format!("E16: Invalid range: 0x{}", data))?;
+        if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+            let filesize = current_file.data.len();
+            if goto_address >= filesize {
+                if filesize > 0 {
+                    goto_address = filesize - 1;
+                } else {
+                    goto_address = 0;
                 }
             }
-            Err(_e) => {
-                // TODO: Add error messages
-            }
+            current_file.cursor.goto(goto_address);
         }
+        return Ok(());
+    }
+    if let Some(name) = command
+        .strip_prefix(":colorscheme ")
+        .or_else(|| command.strip_prefix(":colo "))
+    {
+        let name = name.trim();
+        let theme = match name {
+            "user" => Theme::load_user()
+                .unwrap_or_else(|| Err(format!("{} not found", THEME_PATH)))
+                .map_err(|e| format!("Error in theme {}", e))?,
+            name => Theme::builtin(name).ok_or_else(|| {
+                format!(
+                    "E185: Cannot find color scheme '{}', expected one of: {}, user",
+                    name,
+                    Theme::NAMES.join(", ")
+                )
+            })?,
+        };
+        app.set_theme(theme);
+        return Ok(());
+    }
+    if command.trim() == ":e!" {
+        if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+            current_file.reload().map_err(|e| {
+                format!(
+                    "E484: Can't open file {}: {}",
+                    current_file.path,
+                    describe_io_error(&e)
+                )
+            })?;
+            let message = format!("\"{}\" {}B", current_file.name, current_file.data.len());
+            app.info(message);
+        }
+        return Ok(());
+    }
+    if let Some(path) = command.strip_prefix(":read-dump ") {
+        return read_dump(app, path.trim());
+    }
+    if let Some(data) = command.strip_prefix(":view ") {
+        app.open(data, true)
+            .map_err(|e| format!("E484: Can't open file {}: {}", data, describe_io_error(&e)))?;
+        app.tabs_index = app.tabs.len() - 1;
+        return Ok(());
     }
     if let Some(data) = command.strip_prefix(":e ") {
-        if let Err(_e) = app.open(data) {
-        } else {
-            app.tabs_index = app.tabs.len() - 1;
+        app.open(data, false)
+            .map_err(|e| format!("E484: Can't open file {}: {}", data, describe_io_error(&e)))?;
+        app.tabs_index = app.tabs.len() - 1;
+        return Ok(());
+    }
+    if let Some(cmd) = command.strip_prefix(":set ") {
+        return handle_set(app, cmd.trim());
+    }
+    let trimmed = command.trim();
+    let (name, args) = match trimmed.find(' ') {
+        Some(idx) => (&trimmed[..idx], trimmed[idx..].trim()),
+        None => (trimmed, ""),
+    };
+    match name {
+        ":map" | ":noremap" => return handle_map(app, args),
+        ":unmap" => {
+            let key = map_key(args)?;
+            if !app.mappings.remove(key) {
+                return Err(format!("E31: No such mapping: {}", args));
+            }
+            return Ok(());
         }
+        _ => {}
     }
-    if let Some(data) = command.strip_prefix(":w ") {
-        if let Err(_e) = app.write(data) {
-            //TODO: handle errors
+    if let Some(count) = command.strip_prefix(":earlier ") {
+        let count = count
+            .trim()
+            .parse()
+            .map_err(|_e| format!("E474: Invalid argument: {}", count))?;
+        if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+            current_file.check_modifiable()?;
+            current_file.earlier(count);
         }
-        return;
+        return Ok(());
     }
-    if let Some(cmd) = command.strip_prefix(":set ") {
-        handle_set(app, cmd)
+    if let Some(count) = command.strip_prefix(":later ") {
+        let count = count
+            .trim()
+            .parse()
+            .map_err(|_e| format!("E474: Invalid argument: {}", count))?;
+        if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+            current_file.check_modifiable()?;
+            current_file.later(count);
+        }
+        return Ok(());
     }
     match command.trim() {
         ":bnext" | ":bn" => {
@@ -60,15 +494,65 @@ pub fn handle_command(app: &mut App, terminal: &mut Term) {
         ":bprev" | ":bp" => {
             app.tab_previous();
         }
-        ":bd" => {
+        ":bd" | ":bdelete" | ":bd!" | ":bdelete!" => {
+            if !command.trim().ends_with('!') && current_modified(app) {
+                return Err(format!(
+                    "E89: No write since last change for buffer {} (add ! to override)",
+                    app.tabs_index + 1
+                ));
+            }
             app.tabs.remove(app.tabs_index);
             if app.tabs_index == app.tabs.len() {
-                app.tabs_index -= 1;
+                app.tabs_index = app.tabs_index.saturating_sub(1);
             }
             if app.tabs.is_empty() {
                 app.mode = Mode::Quit;
             }
         }
+        ":undo" | ":u" => {
+            if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+                current_file.check_modifiable()?;
+                if !current_file.undo() {
+                    app.info("Already at oldest change");
+                }
+            }
+        }
+        ":redo" | ":red" => {
+            if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+                current_file.check_modifiable()?;
+                if !current_file.redo() {
+                    app.info("Already at newest change");
+                }
+            }
+        }
+        ":checkt" | ":checktime" => app.check_time(true),
+        ":colo" | ":colorscheme" => app.info(app.theme.name.clone()),
+        ":changes" => {
+            if let Tab::File(current_file) = &app.tabs[app.tabs_index] {
+                let changes = current_file.changes();
+                let message = match changes.len() {
+                    0 => String::from("No changes"),
+                    count => format!(
+                        "{} change{}: {}",
+                        count,
+                        if count == 1 { "" } else { "s" },
+                        changes
+                            .iter()
+                            .map(|c| match c.len() {
+                                0 => format!("deleted at 0x{:X}", c.start),
+                                1 => format!("0x{:X}", c.start),
+                                _ => format!("0x{:X}-0x{:X}", c.start, c.end - 1),
+                            })
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    ),
+                };
+                app.info(message);
+            }
+        }
+        ":noh" | ":nohlsearch" => {
+            app.search_highlight = false;
+        }
         ":topen" => {
             app.options.type_inspector = true;
         }
@@ -87,50 +571,313 @@ pub fn handle_command(app: &mut App, terminal: &mut Term) {
             app.tabs.push(Tab::Help);
             app.tabs_index = app.tabs.len() - 1;
         }
-        cmd if cmd.starts_with(":b") => {
-            if let Ok(num) = cmd[2..].parse::<usize>() {
-                if num > 0 {
-                    app.goto_tab(num - 1);
-                }
+        cmd if cmd.starts_with(":b") && cmd[2..].parse::<usize>().is_ok() => {
+            let num = cmd[2..].parse::<usize>().unwrap_or(0);
+            if num == 0 || num > app.tabs.len() {
+                return Err(format!("E86: Buffer {} does not exist", num));
             }
+            app.goto_tab(num - 1);
         }
-        _ => {
-            match command_chars.next() {
-                Some(':') => {
-                    for (index, c) in command_chars.enumerate() {
-                        match c {
-                            'q' => {
-                                // NOTE: Unless forced, quit may be undone later
-                                app.mode = Mode::Quit;
-                            }
-                            'w' => {
-                                if let Err(_e) = app.write(None) {
-                                    //TODO: Add error logging
-                                }
-                            }
-                            'a' => {
-                                // TODO: Handle marking all
-                            }
-                            '!' => {
-                                if index == 0 {
-                                    app.mode = Mode::Bash;
-                                    app.command = command[2..].to_string();
-                                    return;
-                                } else {
-                                    force_command = true;
-                                }
-                            }
-                            _ => {}
+        _ => match command_chars.next() {
+            Some(':') => {
+                let text = command[1..].trim();
+                if let Some(shell_command) = text.strip_prefix('!') {
+                    app.mode = Mode::Bash;
+                    app.command = shell_command.to_string();
+                    return Ok(());
+                }
+                // Writing commands take a file name, e.g. "w! out.bin"
+                let (text, file) = match text.find(char::is_whitespace) {
+                    Some(idx) => (&text[..idx], Some(text[idx..].trim())),
+                    None => (text, None),
+                };
+                // Example usage: "q!" will force quit
+                let (name, force) = match text.strip_suffix('!') {
+                    Some(name) => (name, true),
+                    None => (text, false),
+                };
+                let takes_file =
+                    matches!(name, "w" | "write" | "wq" | "x" | "xit" | "exi" | "exit");
+                if file.is_some() && !takes_file {
+                    return Err(format!(
+                        "E492: Not an editor command: {}",
+                        command[1..].trim()
+                    ));
+                }
+                match name {
+                    "" => {}
+                    // Buffers are never hidden, so quitting always closes all of them
+                    "q" | "quit" | "qa" | "qall" | "quita" | "quitall" => {
+                        check_modified(app, force)?;
+                        app.mode = Mode::Quit;
+                    }
+                    "w" | "write" => write(app, file, force)?,
+                    "wa" | "wall" => write_all(app, force)?,
+                    "wq" => {
+                        write(app, file, force)?;
+                        check_modified(app, force)?;
+                        app.mode = Mode::Quit;
+                    }
+                    "x" | "xit" | "exi" | "exit" => {
+                        if file.is_some() || current_modified(app) {
+                            write(app, file, force)?;
                         }
+                        check_modified(app, force)?;
+                        app.mode = Mode::Quit;
                     }
+                    "wqa" | "wqall" | "xa" | "xall" => {
+                        write_all(app, force)?;
+                        app.mode = Mode::Quit;
+                    }
+                    _ => return Err(format!("E492: Not an editor command: {}", text)),
                 }
-                Some('/') => {
-                    let search_query = &command[1..];
+            }
+            Some('/') => {
+                let search_query = &command[1..];
+                let query = parse_search_query(search_query, app.options.big_endian)
+                    .map_err(|e| format!("E383: Invalid search string: {}", e))?;
+                app.last_search = Some(Search {
+                    text: search_query.to_string(),
+                    query,
+                });
+                search::jump_to_match(app, true)?;
+            }
+            _ => {}
+        },
+    }
+
+    Ok(())
+}
+
+fn current_modified(app: &App) -> bool {
+    match &app.tabs[app.tabs_index] {
+        Tab::File(current_file) => current_file.is_modified(),
+        _ => false,
+    }
+}
+
+/// Refuse to drop unsaved changes in any buffer unless `force` is given
+fn check_modified(app: &App, force: bool) -> CommandResult {
+    if force {
+        return Ok(());
+    }
+    if current_modified(app) {
+        return Err(String::from(
+            "E37: No write since last change (add ! to override)",
+        ));
+    }
+    for tab in &app.tabs {
+        if let Tab::File(file) = tab {
+            if file.is_modified() {
+                return Err(format!(
+                    "E162: No write since last change for buffer \"{}\"",
+                    file.name
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+const ZERO_BLOCK: usize = 0x10000;
+
+/// Write the bytes of a hex dump over the current buffer at the offsets it lists,
+/// growing the buffer if they go past its end
+fn read_dump(app: &mut App, path: &str) -> CommandResult {
+    let current_file = match &mut app.tabs[app.tabs_index] {
+        Tab::File(current_file) => current_file,
+        _ => return Ok(()),
+    };
+    current_file.check_modifiable()?;
+    let text = fs::read(tilde_expand(path).unwrap_or_default())
+        .map_err(|e| format!("E484: Can't open file {}: {}", path, describe_io_error(&e)))?;
+    let chunks = dump::parse_dump(&String::from_utf8_lossy(&text))
+        .map_err(|e| format!("E475: Invalid dump: {}", e))?;
+    let mut count = 0;
+    // Gaps are filled with one block of zeros repeated, so they take no memory
+    let mut zero_block = None;
+    for (offset, data) in chunks {
+        let len = current_file.data.len();
+        if offset > len {
+            let zeros = *zero_block
+                .get_or_insert_with(|| current_file.data.append_bytes(&[0; ZERO_BLOCK])[0]);
+            let gap = offset - len;
+            // A typo in an offset shouldn't take the editor down with it
+            let mut pieces = Vec::new();
+            pieces
+                .try_reserve_exact(gap / ZERO_BLOCK + 1)
+                .map_err(|_e| format!("E342: Out of memory! (offset 0x{:X})", offset))?;
+            for start in (0..gap).step_by(ZERO_BLOCK) {
+                pieces.push(Piece {
+                    len: usize::min(ZERO_BLOCK, gap - start),
+                    ..zeros
+                });
+            }
+            current_file.replace_pieces(len, 0, pieces);
+        }
+        let overwritten = usize::min(data.len(), current_file.data.len() - offset);
+        current_file.splice(offset, overwritten, &data);
+        count += data.len();
+    }
+    current_file.history.commit();
+    app.info(format!("{}B read from \"{}\"", count, path));
+    Ok(())
+}
+
+/// Run the shell command left in `app.command` by `:!`, showing the last line it
+/// printed or its exit status
+pub fn run_shell(app: &mut App) {
+    let output = if cfg!(target_os = "windows") {
+        Command::new("cmd").args(["/C", &app.command[..]]).output()
+    } else {
+        Command::new("sh").arg("-c").arg(&app.command[..]).output()
+    };
+    app.command = String::new();
+    app.mode = Mode::Default;
+    match output {
+        Ok(output) if output.status.success() => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            app.info(stdout.trim_end().lines().last().unwrap_or(""));
+        }
+        Ok(output) => match output.status.code() {
+            Some(code) => app.error(format!("shell returned {}", code)),
+            None => app.error("shell killed by signal"),
+        },
+        Err(e) => app.error(format!(
+            "E282: Cannot execute shell: {}",
+            describe_io_error(&e)
+        )),
+    }
+}
+
+/// Write every modified buffer back to the path it was opened from
+fn write_all(app: &mut App, force: bool) -> CommandResult {
+    let current = app.tabs_index;
+    let mut result = Ok(());
+    for index in 0..app.tabs.len() {
+        if let Tab::File(file) = &app.tabs[index] {
+            if file.is_modified() {
+                app.tabs_index = index;
+                result = write(app, None, force);
+                if result.is_err() {
+                    break;
                 }
-                _ => {}
             }
         }
     }
+    app.tabs_index = current;
+    result
+}
+
+/// Write the current buffer, reporting the number of bytes written. Unless
+/// `force` is given, refuse to overwrite changes another program made to its file.
+fn write(app: &mut App, filename: Option<&str>, force: bool) -> CommandResult {
+    let current_file = match &app.tabs[app.tabs_index] {
+        Tab::File(current_file) => current_file,
+        _ => return Err(String::from("E32: No file name")),
+    };
+    // Other files may be written freely, only the buffer's own one is protected
+    let own_file = filename.is_none_or(|filename| current_file.is_own_path(filename));
+    if own_file && !force && current_file.read_only {
+        return Err(String::from(
+            "E45: 'readonly' option is set (add ! to override)",
+        ));
+    }
+    if own_file && !force && current_file.changed_on_disk() {
+        return Err(format!(
+            "E949: File \"{}\" changed since editing started (add ! to override)",
+            current_file.name
+        ));
+    }
+    let name = filename.unwrap_or(&current_file.path).to_string();
+    match app.write(filename) {
+        Ok(written) => {
+            app.info(format!("\"{}\" {}B written", name, written));
+            Ok(())
+        }
+        Err(e) => Err(format!(
+            "E212: Can't open file for writing: {}",
+            describe_io_error(&e)
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Buffer;
+
+    fn file(len: usize) -> NailFile {
+        let mut file = NailFile::open("/nonexistent/nail-test", false).unwrap();
+        file.data = Buffer::from_vec(vec![0; len]);
+        file.cursor.goto(usize::min(5, len.saturating_sub(1)));
+        file
+    }
+
+    #[test]
+    fn parse_address_reads_hex_cursor_and_end() {
+        let file = file(0x10);
+        assert_eq!(parse_address(&file, "0x1f d"), Ok(Some((0x1F, " d"))));
+        assert_eq!(parse_address(&file, ". y"), Ok(Some((5, " y"))));
+        assert_eq!(parse_address(&file, "$d"), Ok(Some((0xF, "d"))));
+        // Hex digits are read greedily, so a command needs a space after a hex address
+        assert_eq!(parse_address(&file, "0x10d"), Ok(Some((0x10D, ""))));
+        assert_eq!(parse_address(&file, "d"), Ok(None));
+        assert!(parse_address(&file, "0x").is_err());
+        assert!(parse_address(&file, "'<").is_err());
+    }
+
+    #[test]
+    fn parse_range_orders_and_bounds_addresses() {
+        let file = file(0x10);
+        assert_eq!(parse_range(&file, "0x2,0x8 d"), Ok((Some(0x2..0x9), " d")));
+        assert_eq!(parse_range(&file, "0x3 d"), Ok((Some(0x3..0x4), " d")));
+        assert_eq!(parse_range(&file, "%d"), Ok((Some(0x0..0x10), "d")));
+        assert_eq!(parse_range(&file, ".,$ y"), Ok((Some(0x5..0x10), " y")));
+        assert_eq!(parse_range(&file, "d"), Ok((None, "d")));
+        assert_eq!(
+            parse_range(&file, "0x8,0x2 d"),
+            Err(String::from("E493: Backwards range given"))
+        );
+        assert!(parse_range(&file, "0x2, d").is_err());
+        assert!(parse_range(&file, "0xffffffffffffffff d").is_err());
+        // Ranges past the end are refused by the command, a lone address is a goto
+        assert_eq!(
+            parse_range(&file, "0x8,0x20 d"),
+            Ok((Some(0x8..0x21), " d"))
+        );
+    }
+
+    #[test]
+    fn range_commands_refuse_bytes_past_the_end() {
+        let mut app = App::new();
+        app.tabs = vec![Tab::File(Box::new(file(0x10)))];
+        app.tabs_index = 0;
+        for command in [":0x8,0x20 d", ":0x100,0x200 fill 00", ":0x10 y"] {
+            app.command = String::from(command);
+            assert_eq!(
+                handle_command(&mut app),
+                Err(String::from("E16: Invalid range"))
+            );
+        }
+        app.command = String::from(":0x2,0x8 d");
+        assert_eq!(handle_command(&mut app), Ok(()));
+        if let Tab::File(file) = &app.tabs[0] {
+            assert_eq!(file.data.len(), 0x9);
+        }
+    }
 
-    // TODO: Add checking if file needs to be saved + check for force quit
+    #[test]
+    fn range_edits_that_change_nothing_leave_the_file_unmodified() {
+        let mut app = App::new();
+        app.tabs = vec![Tab::File(Box::new(file(0)))];
+        app.tabs_index = 0;
+        for command in [":d", ":%d", ":%fill 00"] {
+            app.command = String::from(command);
+            assert_eq!(handle_command(&mut app), Ok(()));
+        }
+        if let Tab::File(file) = &app.tabs[0] {
+            assert!(!file.is_modified());
+        }
+    }
 }
@@ -0,0 +1,401 @@
+use std::io;
+use std::io::{Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use crate::buffer::Buffer;
+use crate::file::ascii_char;
+use crate::util::parse_hex_bytes;
+
+/// Output formats of `nail --dump`
+#[derive(Clone, Copy)]
+pub enum DumpFormat {
+    /// The layout drawn by the editor
+    Nail,
+    Xxd,
+    /// Bare hex digits like `xxd -p`
+    Plain,
+    /// A C array like `xxd -i`
+    C,
+}
+
+impl DumpFormat {
+    pub const NAMES: &'static [&'static str] = &["nail", "xxd", "plain", "c"];
+
+    pub fn new(name: &str) -> Option<DumpFormat> {
+        match name {
+            "nail" => Some(DumpFormat::Nail),
+            "xxd" => Some(DumpFormat::Xxd),
+            "plain" => Some(DumpFormat::Plain),
+            "c" => Some(DumpFormat::C),
+            _ => None,
+        }
+    }
+
+    /// Bytes per line when no column count is given, the same as xxd's
+    fn default_cols(self) -> usize {
+        match self {
+            DumpFormat::Nail | DumpFormat::Xxd => 0x10,
+            DumpFormat::Plain => 30,
+            DumpFormat::C => 12,
+        }
+    }
+}
+
+/// Offset column of the editor's layout
+pub fn offset_label(offset: usize) -> String {
+    format!("{:08X} ", offset)
+}
+
+/// Column header of the editor's layout for rows of `cols` bytes, labelling
+/// each group of `group` bytes
+pub fn header(cols: usize, group: usize) -> String {
+    let hex = (0..cols)
+        .step_by(group)
+        .map(|i| {
+            let width = hex_width(usize::min(group, cols - i), group);
+            format!("{:<width$}", format!("{:02X}", i), width = width)
+        })
+        .collect::<Vec<String>>()
+        .join(" ");
+    let ascii = (0..cols)
+        .map(|i| format!("{:X}", i % 0x10))
+        .collect::<String>();
+    format!("{}{}  {}", " ".repeat(9), hex, ascii)
+}
+
+/// Position of byte `index` of a row in the hex column, where bytes are grouped
+/// `group` at a time with a space between groups
+pub fn hex_column(index: usize, group: usize) -> usize {
+    index * 2 + index / group
+}
+
+/// Width of the hex column for a row of `len` bytes
+pub fn hex_width(len: usize, group: usize) -> usize {
+    match len {
+        0 => 0,
+        len => hex_column(len - 1, group) + 2,
+    }
+}
+
+/// Spaces lining up the ASCII column after a row of only `len` out of `cols` bytes
+pub fn hex_padding(len: usize, cols: usize, group: usize) -> String {
+    " ".repeat(hex_width(cols, group) - hex_width(len, group))
+}
+
+fn nail_row(offset: usize, data: &[u8], cols: usize) -> String {
+    let hex = data
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let ascii = data
+        .iter()
+        .map(|byte| ascii_char(*byte))
+        .collect::<String>();
+    format!(
+        "{}{}{}  {}",
+        offset_label(offset),
+        hex,
+        hex_padding(data.len(), cols, 1),
+        ascii
+    )
+}
+
+fn xxd_row(offset: usize, data: &[u8], cols: usize) -> String {
+    let mut hex = String::new();
+    for (i, byte) in data.iter().enumerate() {
+        if i > 0 && i % 2 == 0 {
+            hex.push(' ');
+        }
+        hex.push_str(&format!("{:02x}", byte));
+    }
+    let ascii = data
+        .iter()
+        .map(|&byte| match byte {
+            0x20..=0x7E => byte as char,
+            _ => '.',
+        })
+        .collect::<String>();
+    let width = cols * 2 + (cols - 1) / 2;
+    format!("{:08x}: {:<width$}  {}", offset, hex, ascii, width = width)
+}
+
+/// Name `xxd -i` gives the array for a file
+fn c_name(filename: &str) -> String {
+    let name = filename
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("__{}", name)
+    } else {
+        name
+    }
+}
+
+/// Write the bytes in `range` to `out` as text. Rows start at `range.start` and
+/// are labelled with their offset in the file.
+pub fn dump<W: Write>(
+    data: &Buffer,
+    range: Range<usize>,
+    format: DumpFormat,
+    cols: Option<usize>,
+    filename: &str,
+    out: &mut W,
+) -> io::Result<()> {
+    let cols = cols.unwrap_or_else(|| format.default_cols());
+    let range = usize::min(range.start, data.len())..usize::min(range.end, data.len());
+    match format {
+        DumpFormat::Nail => writeln!(out, "{}", header(cols, 1))?,
+        DumpFormat::C => writeln!(out, "unsigned char {}[] = {{", c_name(filename))?,
+        _ => {}
+    }
+    let mut offset = range.start;
+    while offset < range.end {
+        let row = data.slice(offset, usize::min(cols, range.end - offset))?;
+        if row.is_empty() {
+            break;
+        }
+        match format {
+            DumpFormat::Nail => writeln!(out, "{}", nail_row(offset, &row, cols))?,
+            DumpFormat::Xxd => writeln!(out, "{}", xxd_row(offset, &row, cols))?,
+            DumpFormat::Plain => {
+                let hex = row.iter().map(|byte| format!("{:02x}", byte));
+                writeln!(out, "{}", hex.collect::<String>())?
+            }
+            DumpFormat::C => {
+                let hex = row.iter().map(|byte| format!("0x{:02x}", byte));
+                let separator = if offset + row.len() < range.end {
+                    ","
+                } else {
+                    ""
+                };
+                writeln!(
+                    out,
+                    "  {}{}",
+                    hex.collect::<Vec<String>>().join(", "),
+                    separator
+                )?
+            }
+        }
+        offset += row.len();
+    }
+    if let DumpFormat::C = format {
+        writeln!(out, "}};")?;
+        writeln!(
+            out,
+            "unsigned int {}_len = {};",
+            c_name(filename),
+            range.len()
+        )?;
+    }
+    Ok(())
+}
+
+/// Parse a hex dump in the editor's layout, xxd's or as bare hex back into runs
+/// of bytes and the offsets they belong at. Lines without an offset continue
+/// where the previous one ended.
+pub fn parse_dump(text: &str) -> Result<Vec<(usize, Vec<u8>)>, String> {
+    let mut chunks: Vec<(usize, Vec<u8>)> = Vec::new();
+    let mut offset = 0;
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || is_header(line) {
+            continue;
+        }
+        let (line_offset, hex) = split_offset(line);
+        if let Some(line_offset) = line_offset {
+            offset = line_offset;
+        }
+        if hex.trim().is_empty() {
+            continue;
+        }
+        let bytes = parse_hex_bytes(hex)
+            .ok_or_else(|| format!("line {}: not a hex dump: {}", i + 1, line.trim()))?;
+        let end = offset
+            .checked_add(bytes.len())
+            .ok_or_else(|| format!("line {}: offset too large: {}", i + 1, line.trim()))?;
+        match chunks.last_mut() {
+            Some((start, data)) if *start + data.len() == offset => data.extend_from_slice(&bytes),
+            _ => chunks.push((offset, bytes)),
+        }
+        offset = end;
+    }
+    Ok(chunks)
+}
+
+/// Write the chunks of a parsed dump to a file at their offsets. Gaps are
+/// skipped over, leaving zeros that take no space on most file systems.
+pub fn write_sparse<W: Write + Seek>(chunks: &[(usize, Vec<u8>)], out: &mut W) -> io::Result<()> {
+    for (offset, data) in chunks {
+        out.seek(SeekFrom::Start(*offset as u64))?;
+        out.write_all(data)?;
+    }
+    Ok(())
+}
+
+/// Write the chunks of a parsed dump to a stream as one file, filling gaps with
+/// zeros. Chunks that go back over earlier ones have to be laid out in memory.
+pub fn write_stream<W: Write>(chunks: &[(usize, Vec<u8>)], out: &mut W) -> io::Result<()> {
+    let in_order = chunks
+        .windows(2)
+        .all(|pair| pair[0].0 + pair[0].1.len() <= pair[1].0);
+    if !in_order {
+        let len = chunks
+            .iter()
+            .map(|(offset, data)| offset + data.len())
+            .max()
+            .unwrap_or(0);
+        let mut file = Vec::new();
+        file.try_reserve_exact(len).map_err(|_e| {
+            io::Error::new(
+                io::ErrorKind::OutOfMemory,
+                "dump too large to lay out in memory, write it to a file with -o",
+            )
+        })?;
+        file.resize(len, 0);
+        write_sparse(chunks, &mut io::Cursor::new(&mut file))?;
+        return out.write_all(&file);
+    }
+
+    let zeros = [0; 0x10000];
+    let mut pos = 0;
+    for (offset, data) in chunks {
+        while pos < *offset {
+            let count = usize::min(zeros.len(), offset - pos);
+            out.write_all(&zeros[..count])?;
+            pos += count;
+        }
+        out.write_all(data)?;
+        pos += data.len();
+    }
+    Ok(())
+}
+
+/// Whether `line` is the column header of the editor's layout
+fn is_header(line: &str) -> bool {
+    let hex = line.trim_start().split("  ").next().unwrap_or("");
+    line.starts_with(' ') && line.trim_end() == header(hex.split(' ').count(), 1)
+}
+
+/// Split the offset off a row of the editor's layout or of xxd's and drop the
+/// text column. Anything else is taken as bare hex.
+fn split_offset(line: &str) -> (Option<usize>, &str) {
+    let (first, rest) = match line.find(' ') {
+        Some(idx) => (&line[..idx], &line[idx + 1..]),
+        None => (line, ""),
+    };
+    // The text column is separated from the bytes by two spaces
+    let (hex, text) = match rest.find("  ") {
+        Some(idx) => (&rest[..idx], Some(rest[idx..].trim_start_matches(' '))),
+        None => (rest, None),
+    };
+    if let Some(offset) = first.strip_suffix(':') {
+        if let Ok(offset) = usize::from_str_radix(offset, 16) {
+            return (Some(offset), hex);
+        }
+    }
+    // Without xxd's colon only a whole row of the editor's layout has an offset:
+    // 8 or more digits, single bytes and a text column no wider than them. Its
+    // leading spaces may have been taken for the separator and trailing ones trimmed.
+    let is_hex = |text: &str| !text.is_empty() && text.chars().all(|c| c.is_ascii_hexdigit());
+    let bytes = hex.split(' ').collect::<Vec<&str>>();
+    let is_row = first.len() >= 8
+        && is_hex(first)
+        && bytes.iter().all(|b| b.len() == 2 && is_hex(b))
+        && text.is_some_and(|text| text.chars().count() <= bytes.len());
+    if is_row {
+        if let Ok(offset) = usize::from_str_radix(first, 16) {
+            return (Some(offset), hex);
+        }
+    }
+    (None, line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO: &[u8] = b"Hello, world!\n\x00\xff";
+
+    fn nail_dump(data: &[u8], range: Range<usize>, cols: usize) -> String {
+        let mut out = Vec::new();
+        let buffer = Buffer::from_vec(data.to_vec());
+        dump(&buffer, range, DumpFormat::Nail, Some(cols), "", &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn parses_nail_rows() {
+        let data = (0..=0xFF).collect::<Vec<u8>>();
+        for cols in [8, 16] {
+            let text = nail_dump(&data, 0x13..0x45, cols);
+            assert_eq!(
+                parse_dump(&text).unwrap(),
+                vec![(0x13, data[0x13..0x45].to_vec())]
+            );
+        }
+    }
+
+    #[test]
+    fn parses_xxd_rows() {
+        // xxd -c5, -g4 and -u
+        let dumps = [
+            "00000000: 4865 6c6c 6f  Hello\n\
+             00000005: 2c20 776f 72  , wor\n\
+             0000000a: 6c64 210a 00  ld!..\n\
+             0000000f: ff            .\n",
+            "00000000: 48656c6c 6f2c2077 6f726c64 210a00ff  Hello, world!...\n",
+            "00000000: 4865 6C6C 6F2C 2077 6F72 6C64 210A 00FF  Hello, world!...\n",
+        ];
+        for text in dumps {
+            assert_eq!(parse_dump(text).unwrap(), vec![(0, HELLO.to_vec())]);
+        }
+    }
+
+    #[test]
+    fn parses_bare_hex() {
+        let text = "48656c6c6f2c20776f726c64\n210a00ff\n";
+        assert_eq!(parse_dump(text).unwrap(), vec![(0, HELLO.to_vec())]);
+    }
+
+    #[test]
+    fn rejects_c_arrays() {
+        let text = "unsigned char h_bin[] = {\n  \
+                    0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64,\n  \
+                    0x21, 0x0a, 0x00, 0xff\n};\nunsigned int h_bin_len = 16;\n";
+        assert!(parse_dump(text).is_err());
+    }
+
+    #[test]
+    fn bare_hex_is_not_an_offset() {
+        // Without the text column of a row the first group is just data
+        assert_eq!(split_offset("deadbeef 00 11"), (None, "deadbeef 00 11"));
+        assert_eq!(
+            split_offset("deadbeef 0011  .."),
+            (None, "deadbeef 0011  ..")
+        );
+        assert_eq!(
+            split_offset("deadbeef 00 11  ..."),
+            (None, "deadbeef 00 11  ...")
+        );
+        assert_eq!(split_offset("deadbeef"), (None, "deadbeef"));
+        assert_eq!(
+            parse_dump("deadbeef 00 11\n").unwrap(),
+            vec![(0, vec![0xDE, 0xAD, 0xBE, 0xEF, 0x00, 0x11])]
+        );
+        assert_eq!(
+            split_offset("deadbeef 00 11  .."),
+            (Some(0xDEADBEEF), "00 11")
+        );
+    }
+
+    #[test]
+    fn rejects_overflowing_offsets() {
+        assert!(parse_dump("ffffffffffffffff: 00 11\n").is_err());
+        assert!(parse_dump("1ffffffffffffffff: 00\n").is_err());
+        assert_eq!(
+            parse_dump("fffffffffffffffd: 00 11\n").unwrap(),
+            vec![(0xFFFFFFFFFFFFFFFD, vec![0x00, 0x11])]
+        );
+    }
+}
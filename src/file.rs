@@ -1,68 +1,378 @@
 use std::cmp::max;
+use std::fs;
+use std::io;
 use std::io::prelude::*;
 use std::io::Cursor;
 use std::io::SeekFrom;
+use std::ops::Range;
+use std::path::Path;
+use std::time::SystemTime;
 
 use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
-use tui::style::{Color, Style};
+use tui::style::Style;
 use tui::text::Span;
 use tui::text::Spans;
 
 use crate::app::App;
+use crate::buffer::{Buffer, Piece};
+use crate::command_handler::{describe_io_error, CommandResult};
+use crate::dump;
+use crate::modes::Mode;
+use crate::search;
+use crate::tilde_expand::tilde_expand;
+use crate::undo::{Change, UndoTree};
 use crate::util::HexCursor;
 
 pub struct File {
     pub name: String,
     pub path: String,
-    pub data: Vec<u8>,
+    pub data: Buffer,
     pub cursor: HexCursor,
     pub scroll_y: usize,
+    pub history: UndoTree,
+    /// Where the active visual selection was started
+    pub visual_anchor: Option<usize>,
+    /// Last visual selection, used by the `'<,'>` command range
+    pub last_selection: Option<Range<usize>>,
+    /// Undo sequence number the file on disk matches
+    pub saved_seq: usize,
+    /// Pieces of the buffer when it was last read or written, to tell which
+    /// bytes changed since
+    pub saved_pieces: Vec<Piece>,
+    /// State of the file on disk when it was last read or written
+    pub disk_stamp: Option<DiskStamp>,
+    /// Whether the user was already told the file changed on disk
+    pub warned_changed: bool,
+    /// Refuse every change and plain `:w`, set by `-R`, `:view` or `:set readonly`
+    pub read_only: bool,
+}
+
+/// What a file on disk looked like at some point, to notice other programs
+/// changing it
+#[derive(PartialEq)]
+pub struct DiskStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    inode: u64,
+}
+
+impl DiskStamp {
+    pub fn of(path: &str) -> Option<DiskStamp> {
+        let metadata = fs::metadata(tilde_expand(path)?).ok()?;
+        Some(DiskStamp {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            inode: inode(&metadata),
+        })
+    }
+}
+
+#[cfg(unix)]
+fn inode(metadata: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.ino()
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &fs::Metadata) -> u64 {
+    0
 }
 
 impl File {
+    /// Open `filename` without reading it. A file that doesn't exist yet is
+    /// created on the first write.
+    pub fn open(filename: &str, read_only: bool) -> io::Result<File> {
+        let expanded_path = tilde_expand(filename).unwrap_or_default();
+        let data = match Buffer::open(expanded_path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Buffer::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(File {
+            saved_pieces: data.pieces().to_vec(),
+            name: if let Some(s) = Path::new(filename).file_name() {
+                s.to_str().unwrap().to_string()
+            } else {
+                filename.to_string()
+            },
+            path: filename.to_string(),
+            cursor: HexCursor::new((0, 0)),
+            data,
+            scroll_y: 0x10,
+            history: UndoTree::new(),
+            saved_seq: 0,
+            disk_stamp: DiskStamp::of(filename),
+            warned_changed: false,
+            read_only,
+            visual_anchor: None,
+            last_selection: None,
+        })
+    }
+
+    /// Throw away every change, including the undo history, and read the file again
+    pub fn reload(&mut self) -> io::Result<()> {
+        let mut file = File::open(&self.path, self.read_only)?;
+        file.cursor.set_cols(self.cursor.cols);
+        file.cursor.goto(usize::min(
+            self.cursor.loc(),
+            file.data.len().saturating_sub(1),
+        ));
+        file.scroll_y = self.scroll_y;
+        *self = file;
+        Ok(())
+    }
+
+    /// Replace `len` bytes at `offset` with `new`, recording the edit in the open undo step
+    pub fn splice(&mut self, offset: usize, len: usize, new: &[u8]) {
+        let new = self.data.append_bytes(new);
+        self.replace_pieces(offset, len, new);
+    }
+
+    /// Like `splice`, with bytes that are already in the buffer
+    pub fn replace_pieces(&mut self, offset: usize, len: usize, new: Vec<Piece>) {
+        if len == 0 && new.is_empty() {
+            return;
+        }
+        let old = self.data.replace(offset, len, new.clone());
+        self.history.record(Change { offset, old, new });
+    }
+
+    pub fn set_byte(&mut self, offset: usize, byte: u8) {
+        self.splice(offset, 1, &[byte]);
+    }
+
+    pub fn undo(&mut self) -> bool {
+        let offset = self.history.undo(&mut self.data);
+        self.cursor_to_change(offset)
+    }
+
+    pub fn redo(&mut self) -> bool {
+        let offset = self.history.redo(&mut self.data);
+        self.cursor_to_change(offset)
+    }
+
+    /// Go back `count` changes in time, like vim's `:earlier`
+    pub fn earlier(&mut self, count: usize) -> bool {
+        let target = self.history.seq().saturating_sub(count);
+        let offset = self.history.goto(&mut self.data, target);
+        self.cursor_to_change(offset)
+    }
+
+    /// Go forward `count` changes in time, like vim's `:later`
+    pub fn later(&mut self, count: usize) -> bool {
+        let target = self.history.seq().saturating_add(count);
+        let offset = self.history.goto(&mut self.data, target);
+        self.cursor_to_change(offset)
+    }
+
+    /// Whether the buffer differs from what was last read or written
+    pub fn is_modified(&self) -> bool {
+        self.history.has_pending() || self.history.seq() != self.saved_seq
+    }
+
+    /// Ranges of bytes changed since the file was last read or written, in order.
+    /// Deletions are empty ranges at the offset of the byte after them.
+    pub fn changes(&self) -> Vec<Range<usize>> {
+        self.data.changes_since(&self.saved_pieces)
+    }
+
+    /// Start of the next change after the cursor, or of the previous one before it
+    pub fn find_change(&self, forward: bool) -> Option<usize> {
+        let loc = self.cursor.loc();
+        let mut starts = self.changes().into_iter().map(|change| change.start);
+        if forward {
+            starts.find(|&start| start > loc)
+        } else {
+            starts.rev().find(|&start| start < loc)
+        }
+    }
+
+    /// The bytes in `range`, with a read error described for the status line
+    pub fn read_range(&self, range: Range<usize>) -> Result<Vec<u8>, String> {
+        self.data
+            .slice(range.start, range.len())
+            .map_err(|e| format!("Can't read \"{}\": {}", self.name, describe_io_error(&e)))
+    }
+
+    /// Whether `path` names the file the buffer was opened from, even if it
+    /// doesn't exist yet
+    pub fn is_own_path(&self, path: &str) -> bool {
+        let path = tilde_expand(path).unwrap_or_default();
+        let original = tilde_expand(&self.path).unwrap_or_default();
+        match (fs::canonicalize(&path), fs::canonicalize(&original)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => path == original,
+        }
+    }
+
+    pub fn check_modifiable(&self) -> CommandResult {
+        if self.read_only {
+            Err(String::from("E21: Cannot make changes, file is read-only"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Mark the current state of the buffer as the one on disk
+    pub fn mark_saved(&mut self) {
+        self.history.commit();
+        self.saved_seq = self.history.seq();
+        self.saved_pieces = self.data.pieces().to_vec();
+        self.disk_stamp = DiskStamp::of(&self.path);
+        self.warned_changed = false;
+    }
+
+    /// Whether another program changed or removed the file since it was last read
+    /// or written
+    pub fn changed_on_disk(&self) -> bool {
+        self.disk_stamp != DiskStamp::of(&self.path)
+    }
+
+    /// Byte range covered by the visual selection while in one of the visual modes
+    pub fn visual_range(&self, mode: &Mode) -> Option<Range<usize>> {
+        let anchor = self.visual_anchor?;
+        let loc = self.cursor.loc();
+        let start = usize::min(anchor, loc);
+        let end = usize::min(usize::max(anchor, loc) + 1, self.data.len());
+        match mode {
+            Mode::Visual => Some(start..end),
+            // Linewise selections cover whole rows
+            Mode::VisualLine => {
+                let cols = self.cursor.cols;
+                Some((start / cols) * cols..usize::min(end.div_ceil(cols) * cols, self.data.len()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Put `data` at the cursor, either overwriting the bytes there (growing the
+    /// file if it runs past the end) or splicing it in
+    pub fn put(&mut self, data: &[u8], overwrite: bool) {
+        let loc = self.cursor.loc();
+        let len = if overwrite {
+            usize::min(data.len(), self.data.len().saturating_sub(loc))
+        } else {
+            0
+        };
+        self.splice(loc, len, data);
+    }
+
+    /// Remove the bytes in `range` as a single undo step
+    pub fn delete_range(&mut self, range: Range<usize>) {
+        self.splice(range.start, range.end - range.start, &[]);
+        self.history.commit();
+        self.cursor
+            .goto(usize::min(range.start, self.data.len().saturating_sub(1)));
+    }
+
+    fn cursor_to_change(&mut self, offset: Option<usize>) -> bool {
+        match offset {
+            Some(offset) => {
+                let last = self.data.len().saturating_sub(1);
+                self.cursor.goto(usize::min(offset, last));
+                true
+            }
+            None => false,
+        }
+    }
+
     pub fn hex_view(&self, app: &App) -> Vec<Spans<'static>> {
         let num_lines = app.line_count;
-        let mut view = self
-            .data
-            .chunks(0x10)
-            .skip(self.scroll_y / 0x10)
-            .take(num_lines)
+        let cols = self.cursor.cols;
+        let group = app.options.group;
+        let visible_range =
+            self.scroll_y..usize::min(self.scroll_y + num_lines * cols, self.data.len());
+        let selection = self.visual_range(&app.mode);
+        let matches = match &app.last_search {
+            Some(search) if app.search_highlight => {
+                search::matches_in(&self.data, &search.query, visible_range)
+            }
+            _ => vec![],
+        };
+        let changes = self.changes();
+        let changes_start = changes.partition_point(|c| c.end < self.scroll_y);
+        let changes = &changes[changes_start..];
+        let loc = self.cursor.loc();
+        let byte_style = |offset: usize, byte: u8| {
+            let in_selection = match &selection {
+                Some(range) => range.contains(&offset),
+                None => false,
+            };
+            let search_match = matches.iter().find(|m| m.contains(&offset));
+            if in_selection {
+                app.theme.selection
+            } else if let Some(search_match) = search_match {
+                // The match under the cursor stands out from the rest
+                if search_match.contains(&loc) {
+                    app.theme.current_match
+                } else {
+                    app.theme.search_match
+                }
+            } else if changes
+                .iter()
+                .take_while(|c| c.start <= offset)
+                .any(|c| c.contains(&offset) || c.start == offset)
+            {
+                app.options
+                    .color
+                    .style(byte, &app.theme)
+                    .patch(app.theme.changed)
+            } else {
+                app.options.color.style(byte, &app.theme)
+            }
+        };
+
+        // Only the rows on screen are read from the buffer
+        let visible = match self.data.slice(self.scroll_y, num_lines * cols) {
+            Ok(visible) => visible,
+            Err(e) => {
+                return vec![Spans::from(Span::styled(
+                    format!("Can't read \"{}\": {}", self.name, describe_io_error(&e)),
+                    app.theme.error,
+                ))]
+            }
+        };
+        let mut view = visible
+            .chunks(cols)
             .enumerate()
             .map(|(i, data)| {
-                vec![
-                    Span::styled(
-                        format!("{:08X} ", self.scroll_y + (i * 0x10)),
-                        Style::default().fg(Color::Black),
-                    ),
-                    Span::raw(format!(
-                        "{:<47}",
-                        data.iter()
-                            .map(|byte: &u8| format!("{:02X}", byte))
-                            .collect::<Vec<String>>()
-                            .join(" ")
-                    )),
-                    Span::raw("  "),
-                    Span::raw(format!(
-                        "{}\n",
-                        data.iter()
-                            .map(|byte| (match *byte {
-                                0..=0x1F | 0x80..=0xA0 | 0x7F => ".".to_string(),
-                                _ => (*byte as char).to_string(),
-                            }))
-                            .collect::<Vec<String>>()
-                            .join("")
-                    )),
-                ]
+                let row_start = self.scroll_y + (i * cols);
+                let mut line = vec![Span::styled(
+                    dump::offset_label(row_start),
+                    app.theme.offset,
+                )];
+                for (j, byte) in data.iter().enumerate() {
+                    let offset = row_start + j;
+                    if j > 0 && j % group == 0 {
+                        // Gaps inside a highlight are styled too so it reads as one block
+                        let style = byte_style(offset, *byte);
+                        let gap_style = if byte_style(offset - 1, data[j - 1]) == style {
+                            style
+                        } else {
+                            Style::default()
+                        };
+                        line.push(Span::styled(" ", gap_style));
+                    }
+                    line.push(Span::styled(
+                        format!("{:02X}", byte),
+                        byte_style(offset, *byte),
+                    ));
+                }
+                line.push(Span::raw(dump::hex_padding(data.len(), cols, group)));
+                line.push(Span::raw("  "));
+                for (j, byte) in data.iter().enumerate() {
+                    line.push(Span::styled(
+                        ascii_char(*byte).to_string(),
+                        byte_style(row_start + j, *byte),
+                    ));
+                }
+                line
             })
-            // .flatten()
             .map(Spans::from)
             .collect::<Vec<Spans<'static>>>();
         view.insert(
             0,
-            Spans::from(Span::styled(
-                "         00 01 02 03 04 05 06 07 08 09 0A 0B 0C 0D 0E 0F  0123456789ABCDEF\n",
-                Style::default().fg(Color::Black),
-            )),
+            Spans::from(Span::styled(dump::header(cols, group), app.theme.offset)),
         );
         if app.options.type_inspector {
             self.append_type_inspector(app, &mut view);
@@ -70,12 +380,16 @@ impl File {
 
         view
     }
-    // cursorX = 10 + ((file.cursor.pos.0 / 2) * 3) + (file.cursor.pos.0 % 2)
 
     fn append_type_inspector(&self, app: &App, view: &mut Vec<Spans<'static>>) {
-        let filesize = self.data.len();
-        let loc = self.cursor.loc();
-        let mut rdr = Cursor::new(&self.data[..]);
+        // Only the largest inspected type (8 bytes) is needed from the buffer
+        let inspected = match self.data.slice(self.cursor.loc(), 8) {
+            Ok(inspected) => inspected,
+            Err(_) => return,
+        };
+        let filesize = inspected.len();
+        let loc = 0;
+        let mut rdr = Cursor::new(&inspected[..]);
         let sbyte: i8;
         let ubyte: u8;
         let mut sshort: i16 = 0;
@@ -143,13 +457,13 @@ impl File {
 
         // Line 1
         let mut line = vec![];
-        line.push(Span::styled(" u8: ", Style::default().fg(Color::Black)));
+        line.push(Span::styled(" u8: ", app.theme.inspector));
         line.push(Span::raw(format!("{:1$} ", ubyte, unsigned_size)));
-        line.push(Span::styled(" i8: ", Style::default().fg(Color::Black)));
+        line.push(Span::styled(" i8: ", app.theme.inspector));
         line.push(Span::raw(format!("{:1$} ", sbyte, signed_size)));
-        line.push(Span::styled("u64: ", Style::default().fg(Color::Black)));
+        line.push(Span::styled("u64: ", app.theme.inspector));
         line.push(Span::raw(format!("{:1$} ", udword, dword_size)));
-        line.push(Span::styled("f32: ", Style::default().fg(Color::Black)));
+        line.push(Span::styled("f32: ", app.theme.inspector));
         line.push(Span::raw(format!(
             "{:1$}\n",
             float_buffer.format(float),
@@ -159,13 +473,13 @@ impl File {
 
         // Line 2
         let mut line = vec![];
-        line.push(Span::styled("u16: ", Style::default().fg(Color::Black)));
+        line.push(Span::styled("u16: ", app.theme.inspector));
         line.push(Span::raw(format!("{:1$} ", ushort, unsigned_size)));
-        line.push(Span::styled("i16: ", Style::default().fg(Color::Black)));
+        line.push(Span::styled("i16: ", app.theme.inspector));
         line.push(Span::raw(format!("{:1$} ", sshort, signed_size)));
-        line.push(Span::styled("i64: ", Style::default().fg(Color::Black)));
+        line.push(Span::styled("i64: ", app.theme.inspector));
         line.push(Span::raw(format!("{:1$} ", sdword, dword_size)));
-        line.push(Span::styled("f64: ", Style::default().fg(Color::Black)));
+        line.push(Span::styled("f64: ", app.theme.inspector));
         line.push(Span::raw(format!(
             "{:1$}\n",
             float_buffer.format(double),
@@ -175,14 +489,23 @@ impl File {
 
         // Line 3
         let mut line = vec![];
-        line.push(Span::styled("u32: ", Style::default().fg(Color::Black)));
+        line.push(Span::styled("u32: ", app.theme.inspector));
         line.push(Span::raw(format!("{:1$} ", uword, unsigned_size)));
-        line.push(Span::styled("i32: ", Style::default().fg(Color::Black)));
+        line.push(Span::styled("i32: ", app.theme.inspector));
         line.push(Span::raw(format!("{:1$} ", sword, signed_size)));
         view.push(Spans::from(line));
     }
 }
 
+/// Character shown for `byte` in the ASCII column
+pub fn ascii_char(byte: u8) -> char {
+    match byte {
+        0..=0x1F | 0x80..=0xA0 | 0x7F => '.',
+        _ => byte as char,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn read_types<R: ReadBytesExt + Seek, T: ByteOrder>(
     rdr: &mut R,
     filesize: usize,
@@ -0,0 +1,137 @@
+use termion::event::Key;
+
+/// Keys standing for a sequence of other keys in normal and visual mode, set
+/// with `:map`. Like vim's `:noremap`, the keys a mapping produces aren't
+/// mapped again.
+pub struct Mappings {
+    maps: Vec<(Key, Vec<Key>)>,
+}
+
+impl Mappings {
+    pub fn new() -> Mappings {
+        Mappings { maps: Vec::new() }
+    }
+
+    pub fn get(&self, key: Key) -> Option<&[Key]> {
+        self.maps
+            .iter()
+            .find(|(lhs, _)| *lhs == key)
+            .map(|(_, rhs)| &rhs[..])
+    }
+
+    pub fn insert(&mut self, key: Key, keys: Vec<Key>) {
+        match self.maps.iter_mut().find(|(lhs, _)| *lhs == key) {
+            Some((_, rhs)) => *rhs = keys,
+            None => self.maps.push((key, keys)),
+        }
+    }
+
+    /// Returns false if `key` wasn't mapped
+    pub fn remove(&mut self, key: Key) -> bool {
+        let len = self.maps.len();
+        self.maps.retain(|(lhs, _)| *lhs != key);
+        self.maps.len() != len
+    }
+
+    /// Every mapping on one line, in the notation `:map` takes
+    pub fn list(&self) -> String {
+        self.maps
+            .iter()
+            .map(|(lhs, rhs)| format!("{} {}", key_name(*lhs), keys_name(rhs)))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+/// Parse keys in vim's notation, e.g. `:q<CR>` or `<C-d>`. Anything in angle
+/// brackets that isn't a key name is taken literally.
+pub fn parse_keys(text: &str) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some(end) = rest.find('>') {
+                if let Some(key) = special_key(&rest[1..end]) {
+                    keys.push(key);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+        keys.push(Key::Char(c));
+        rest = &rest[c.len_utf8()..];
+    }
+    keys
+}
+
+fn special_key(name: &str) -> Option<Key> {
+    let lower = name.to_lowercase();
+    let modified = |prefix: &str| {
+        let mut chars = lower.strip_prefix(prefix)?.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    };
+    if let Some(c) = modified("c-") {
+        return Some(Key::Ctrl(c));
+    }
+    if let Some(c) = modified("a-").or_else(|| modified("m-")) {
+        return Some(Key::Alt(c));
+    }
+    if let Some(n) = lower.strip_prefix('f').and_then(|n| n.parse().ok()) {
+        return match n {
+            1..=12 => Some(Key::F(n)),
+            _ => None,
+        };
+    }
+    let key = match &lower[..] {
+        "cr" | "enter" | "return" => Key::Char('\n'),
+        "esc" => Key::Esc,
+        "space" => Key::Char(' '),
+        "tab" => Key::Char('\t'),
+        "bs" => Key::Backspace,
+        "del" => Key::Delete,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        "lt" => Key::Char('<'),
+        "bar" => Key::Char('|'),
+        _ => return None,
+    };
+    Some(key)
+}
+
+fn key_name(key: Key) -> String {
+    match key {
+        Key::Char('\n') => String::from("<CR>"),
+        Key::Char(' ') => String::from("<Space>"),
+        Key::Char('\t') => String::from("<Tab>"),
+        Key::Char('<') => String::from("<lt>"),
+        Key::Char(c) => c.to_string(),
+        Key::Ctrl(c) => format!("<C-{}>", c),
+        Key::Alt(c) => format!("<A-{}>", c),
+        Key::F(n) => format!("<F{}>", n),
+        Key::Esc => String::from("<Esc>"),
+        Key::Backspace => String::from("<BS>"),
+        Key::Delete => String::from("<Del>"),
+        Key::Up => String::from("<Up>"),
+        Key::Down => String::from("<Down>"),
+        Key::Left => String::from("<Left>"),
+        Key::Right => String::from("<Right>"),
+        Key::Home => String::from("<Home>"),
+        Key::End => String::from("<End>"),
+        Key::PageUp => String::from("<PageUp>"),
+        Key::PageDown => String::from("<PageDown>"),
+        key => format!("{:?}", key),
+    }
+}
+
+pub fn keys_name(keys: &[Key]) -> String {
+    keys.iter().map(|key| key_name(*key)).collect()
+}
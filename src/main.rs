@@ -1,17 +1,30 @@
 #![allow(dead_code)]
 mod app;
+mod args;
+mod buffer;
+mod checksum;
+mod color;
 mod command_handler;
+mod dump;
 mod file;
+mod keymap;
 mod modes;
 mod nail;
+mod rc;
+mod register;
+mod script;
+mod search;
 mod tabs;
+mod theme;
 mod tilde_expand;
+mod undo;
 mod util;
 
 use std::env;
+use std::fs;
 use std::io;
 use std::io::Write;
-use std::process::Command;
+use std::process;
 
 use termion::cursor::Goto;
 use termion::event::Key;
@@ -20,21 +33,50 @@ use termion::raw::IntoRawMode;
 use termion::screen::AlternateScreen;
 use tui::backend::TermionBackend;
 use tui::layout::{Constraint, Direction, Layout, Rect};
-use tui::style::{Color, Style};
 use tui::text::Span;
 use tui::text::Spans;
 use tui::widgets::{Block, Borders, Paragraph, Tabs};
 use tui::Terminal;
 
-use crate::app::{App, AppOptions, Term};
+use crate::app::{App, Message, Term};
+use crate::args::{Action, DumpArgs};
+use crate::buffer::Buffer;
+use crate::command_handler::describe_io_error;
 use crate::modes::Mode;
+use crate::register::Registers;
 use crate::tabs::Tab;
+use crate::theme::Theme;
+use crate::tilde_expand::tilde_expand;
 use crate::util::event::{Event, Events};
 
 #[allow(unused_variables)]
-fn default_mode(events: &Events, app: &mut App, terminal: &mut Term) -> Result<(), failure::Error> {
-    if let Event::Input(input) = events.next()? {
+fn default_mode(
+    event: Event<Key>,
+    app: &mut App,
+    terminal: &mut Term,
+) -> Result<(), failure::Error> {
+    if let Event::Input(input) = event {
+        if let Some(prefix) = app.pending_key.take() {
+            match prefix {
+                Key::Char(']') | Key::Char('[') => change_motion(app, prefix, input),
+                _ => select_register(app, prefix, input),
+            }
+            return Ok(());
+        }
+        if let Key::Char('i')
+        | Key::Char('R')
+        | Key::Char('r')
+        | Key::Char('x')
+        | Key::Char('p')
+        | Key::Char('P') = input
+        {
+            if !modifiable(app) {
+                return Ok(());
+            }
+        }
         match input {
+            Key::Esc => app.register = None,
+            Key::Char('"') | Key::Char(']') | Key::Char('[') => app.pending_key = Some(input),
             Key::Char(':') => {
                 app.mode = Mode::Command;
                 app.command = String::from(":");
@@ -43,71 +85,291 @@ fn default_mode(events: &Events, app: &mut App, terminal: &mut Term) -> Result<(
                 app.mode = Mode::Command;
                 app.command = String::from("/");
             }
-            Key::Char('i') => app.mode = Mode::Insert,
-            Key::Char('R') | Key::Char('r') => app.mode = Mode::Replace,
-            Key::Up | Key::Char('k') => {
+            Key::Char('i') => {
+                app.mode = Mode::Insert;
+                // New bytes are always started from their upper 4 bits
                 if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
-                    current_file.cursor.up();
+                    let loc = current_file.cursor.loc();
+                    current_file.cursor.goto(loc);
                 }
             }
-            Key::PageUp => {
+            Key::Char('R') | Key::Char('r') => app.mode = Mode::Replace,
+            Key::Char('u') => {
+                if !modifiable(app) {
+                    return Ok(());
+                }
                 if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
-                    let filesize = current_file.data.len();
-                    for _bulk_action in 0..34 {
-                        current_file.cursor.up();
+                    if !current_file.undo() {
+                        app.info("Already at oldest change");
                     }
                 }
             }
-            Key::Down | Key::Char('j') => {
+            Key::Ctrl('r') => {
+                if !modifiable(app) {
+                    return Ok(());
+                }
                 if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
-                    let filesize = current_file.data.len();
-                    current_file.cursor.down(filesize);
+                    if !current_file.redo() {
+                        app.info("Already at newest change");
+                    }
                 }
             }
-            Key::PageDown => {
+            Key::Char('x') => {
                 if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
-                    let filesize = current_file.data.len();
-                    for _bulk_action in 0..34 {
-                        current_file.cursor.down(filesize);
+                    let loc = current_file.cursor.loc();
+                    if loc < current_file.data.len() {
+                        let register = app.register.take();
+                        match current_file.read_range(loc..loc + 1) {
+                            Ok(data) => {
+                                app.registers.delete(register, data);
+                                current_file.delete_range(loc..loc + 1);
+                            }
+                            Err(e) => app.error(e),
+                        }
                     }
                 }
             }
-            Key::Left | Key::Char('h') => {
-                if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
-                    current_file.cursor.left();
+            Key::Char('n') => {
+                if let Err(e) = search::jump_to_match(app, true) {
+                    app.error(e);
                 }
             }
-            Key::Right | Key::Char('l') => {
-                if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
-                    let filesize = current_file.data.len();
-                    current_file.cursor.right(filesize);
+            Key::Char('N') => {
+                if let Err(e) = search::jump_to_match(app, false) {
+                    app.error(e);
+                }
+            }
+            Key::Char('p') => put_register(app, true),
+            Key::Char('P') => put_register(app, false),
+            Key::Char('v') => start_visual(app, Mode::Visual),
+            Key::Char('V') => start_visual(app, Mode::VisualLine),
+            key => move_cursor(app, key),
+        }
+    }
+    Ok(())
+}
+
+/// Report an error and return false if the current file is read-only
+fn modifiable(app: &mut App) -> bool {
+    if let Tab::File(current_file) = &app.tabs[app.tabs_index] {
+        if let Err(e) = current_file.check_modifiable() {
+            app.error(e);
+            return false;
+        }
+    }
+    true
+}
+
+/// Finish a `"x` register prefix
+fn select_register(app: &mut App, prefix: Key, input: Key) {
+    if let (Key::Char('"'), Key::Char(name)) = (prefix, input) {
+        if Registers::is_valid(name) {
+            app.register = Some(name);
+        }
+    }
+}
+
+/// `]c` and `[c`: jump to the next or previous change since the file was saved
+fn change_motion(app: &mut App, prefix: Key, input: Key) {
+    if input != Key::Char('c') {
+        return;
+    }
+    if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+        match current_file.find_change(prefix == Key::Char(']')) {
+            Some(offset) => current_file.cursor.goto(offset),
+            None => app.error("No more changes"),
+        }
+    }
+}
+
+/// Keys `key` is mapped to with `:map`, if it's typed in normal or visual mode
+fn mapping(app: &App, key: Key) -> Option<Vec<Key>> {
+    match app.mode {
+        Mode::Default | Mode::Visual | Mode::VisualLine if app.pending_key.is_none() => {
+            app.mappings.get(key).map(<[Key]>::to_vec)
+        }
+        _ => None,
+    }
+}
+
+/// Put the selected register at the cursor as one undo step. `p` overwrites the
+/// bytes under the cursor like replace mode, `P` splices them in like insert mode.
+fn put_register(app: &mut App, overwrite: bool) {
+    let register = app.register.take();
+    if let (Some(data), Tab::File(current_file)) =
+        (app.registers.get(register), &mut app.tabs[app.tabs_index])
+    {
+        if data.is_empty() {
+            return;
+        }
+        let loc = current_file.cursor.loc();
+        current_file.put(data, overwrite);
+        current_file.history.commit();
+        current_file.cursor.goto(loc + data.len() - 1);
+    }
+}
+
+/// Handle the cursor movement keys shared by default and visual mode
+fn move_cursor(app: &mut App, key: Key) {
+    match key {
+        Key::Up | Key::Char('k') => {
+            if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+                current_file.cursor.up();
+            }
+        }
+        Key::PageUp => {
+            if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+                for _bulk_action in 0..34 {
+                    current_file.cursor.up();
                 }
             }
-            Key::Char('w') => {
+        }
+        Key::Down | Key::Char('j') => {
+            if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+                let filesize = current_file.data.len();
+                current_file.cursor.down(filesize);
+            }
+        }
+        Key::PageDown => {
+            if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+                let filesize = current_file.data.len();
+                for _bulk_action in 0..34 {
+                    current_file.cursor.down(filesize);
+                }
+            }
+        }
+        Key::Left | Key::Char('h') => {
+            if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+                current_file.cursor.left();
+            }
+        }
+        Key::Right | Key::Char('l') => {
+            if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+                let filesize = current_file.data.len();
+                current_file.cursor.right(filesize);
+            }
+        }
+        Key::Char('w') => {
+            if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+                let filesize = current_file.data.len();
+                current_file.cursor.next_word(filesize);
+            }
+        }
+        Key::Char('b') => {
+            if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+                current_file.cursor.prev_word();
+            }
+        }
+        Key::Char('G') => {
+            if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+                current_file
+                    .cursor
+                    .goto(current_file.data.len().saturating_sub(1));
+            }
+        }
+        _ => {}
+    }
+}
+
+fn start_visual(app: &mut App, mode: Mode) {
+    if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+        current_file.visual_anchor = Some(current_file.cursor.loc());
+        app.mode = mode;
+    }
+}
+
+/// Leave visual mode, remembering the selection for `'<,'>`
+fn end_visual(app: &mut App) {
+    if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+        if let Some(range) = current_file.visual_range(&app.mode) {
+            current_file.last_selection = Some(range);
+        }
+        current_file.visual_anchor = None;
+    }
+    app.mode = Mode::Default;
+}
+
+#[allow(unused_variables)]
+fn visual_mode(
+    event: Event<Key>,
+    app: &mut App,
+    terminal: &mut Term,
+) -> Result<(), failure::Error> {
+    if let Event::Input(input) = event {
+        if let Some(prefix) = app.pending_key.take() {
+            match prefix {
+                Key::Char(']') | Key::Char('[') => change_motion(app, prefix, input),
+                _ => select_register(app, prefix, input),
+            }
+            return Ok(());
+        }
+        match input {
+            Key::Esc => end_visual(app),
+            Key::Char('"') | Key::Char(']') | Key::Char('[') => app.pending_key = Some(input),
+            Key::Char('v') => match app.mode {
+                Mode::Visual => end_visual(app),
+                _ => app.mode = Mode::Visual,
+            },
+            Key::Char('V') => match app.mode {
+                Mode::VisualLine => end_visual(app),
+                _ => app.mode = Mode::VisualLine,
+            },
+            Key::Char('o') => {
+                // Jump to the other end of the selection
                 if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
-                    let filesize = current_file.data.len();
-                    current_file.cursor.next_word(filesize);
+                    if let Some(anchor) = current_file.visual_anchor {
+                        current_file.visual_anchor = Some(current_file.cursor.loc());
+                        current_file.cursor.goto(anchor);
+                    }
                 }
             }
-            Key::Char('b') => {
+            Key::Char(':') => {
+                end_visual(app);
+                app.mode = Mode::Command;
+                app.command = String::from(":'<,'>");
+            }
+            Key::Char('y') => {
+                end_visual(app);
                 if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
-                    current_file.cursor.prev_word();
+                    if let Some(range) = current_file.last_selection.clone() {
+                        let register = app.register.take();
+                        current_file.cursor.goto(range.start);
+                        match current_file.read_range(range) {
+                            Ok(data) => app.registers.yank(register, data),
+                            Err(e) => app.error(e),
+                        }
+                    }
                 }
             }
-            Key::Char('G') => {
+            Key::Char('d') | Key::Char('x') => {
+                if !modifiable(app) {
+                    return Ok(());
+                }
+                end_visual(app);
                 if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
-                    current_file
-                        .cursor
-                        .goto(current_file.data.len().saturating_sub(1));
+                    if let Some(range) = current_file.last_selection.clone() {
+                        let register = app.register.take();
+                        match current_file.read_range(range.clone()) {
+                            Ok(data) => {
+                                app.registers.delete(register, data);
+                                current_file.delete_range(range);
+                            }
+                            Err(e) => app.error(e),
+                        }
+                    }
                 }
             }
-            _ => {}
+            key => move_cursor(app, key),
         }
     }
     Ok(())
 }
 
-fn command_mode(events: &Events, app: &mut App, terminal: &mut Term) -> Result<(), failure::Error> {
+fn command_mode(
+    event: Event<Key>,
+    app: &mut App,
+    terminal: &mut Term,
+) -> Result<(), failure::Error> {
     // Move cursor to proper position
     write!(
         terminal.backend_mut(),
@@ -115,12 +377,17 @@ fn command_mode(events: &Events, app: &mut App, terminal: &mut Term) -> Result<(
         Goto(1 + app.command.len() as u16, app.size.height)
     )?;
     // Command mode event handling
-    if let Event::Input(input) = events.next()? {
+    if let Event::Input(input) = event {
         match input {
-            Key::Esc => app.mode = Mode::Default,
+            Key::Esc => {
+                app.mode = Mode::Default;
+                app.command = String::new();
+            }
             Key::Char('\n') => {
                 app.mode = Mode::Default;
-                command_handler::handle_command(app, terminal);
+                if let Err(e) = command_handler::handle_command(app) {
+                    app.error(e);
+                }
                 if let Mode::Default = app.mode {
                     if app.tabs.is_empty() {
                         app.mode = Mode::Title;
@@ -129,7 +396,8 @@ fn command_mode(events: &Events, app: &mut App, terminal: &mut Term) -> Result<(
             }
             Key::Char(c) => app.command.push(c),
             Key::Backspace => {
-                if app.command.pop().unwrap() == ':' && app.command.is_empty() {
+                app.command.pop();
+                if app.command.is_empty() {
                     if app.tabs.is_empty() {
                         app.mode = Mode::Title;
                     } else {
@@ -144,10 +412,41 @@ fn command_mode(events: &Events, app: &mut App, terminal: &mut Term) -> Result<(
 }
 
 #[allow(unused_variables)]
-fn write_mode(events: &Events, app: &mut App, terminal: &mut Term) -> Result<(), failure::Error> {
-    if let Event::Input(input) = events.next()? {
+fn write_mode(event: Event<Key>, app: &mut App, terminal: &mut Term) -> Result<(), failure::Error> {
+    if let Event::Input(input) = event {
+        // Insert mode lets the cursor sit one byte past the end so bytes can be appended
+        let append = if let Mode::Insert = app.mode { 1 } else { 0 };
+        if let Some(Key::Ctrl('r')) = app.pending_key.take() {
+            // ctrl+r {register} puts a register, splicing in insert mode and
+            // overwriting in replace mode
+            if let (Key::Char(name), Tab::File(current_file)) =
+                (input, &mut app.tabs[app.tabs_index])
+            {
+                if let Some(data) = app.registers.get(Some(name)) {
+                    let loc = current_file.cursor.loc();
+                    current_file.put(data, append == 0);
+                    current_file.cursor.goto(loc + data.len());
+                    let filesize = current_file.data.len();
+                    if append == 0 && current_file.cursor.loc() >= filesize {
+                        current_file.cursor.goto(filesize.saturating_sub(1));
+                    }
+                }
+            }
+            return Ok(());
+        }
         match input {
-            Key::Esc => app.mode = Mode::Default,
+            Key::Ctrl('r') => app.pending_key = Some(input),
+            Key::Esc => {
+                app.mode = Mode::Default;
+                if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+                    // Everything typed in this session is undone in one step
+                    current_file.history.commit();
+                    let filesize = current_file.data.len();
+                    if current_file.cursor.loc() >= filesize {
+                        current_file.cursor.goto(filesize.saturating_sub(1));
+                    }
+                }
+            }
             Key::Up => {
                 if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
                     current_file.cursor.up();
@@ -156,7 +455,8 @@ fn write_mode(events: &Events, app: &mut App, terminal: &mut Term) -> Result<(),
             Key::Down => {
                 if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
                     let filesize = current_file.data.len();
-                    current_file.cursor.down(filesize);
+                    current_file.cursor.down(filesize + append);
+                    current_file.cursor.clamp_append(filesize);
                 }
             }
             Key::Left => {
@@ -167,12 +467,12 @@ fn write_mode(events: &Events, app: &mut App, terminal: &mut Term) -> Result<(),
             Key::Right => {
                 if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
                     let filesize = current_file.data.len();
-                    current_file.cursor.right(filesize);
+                    current_file.cursor.right(filesize + append);
+                    current_file.cursor.clamp_append(filesize);
                 }
             }
             Key::PageUp => {
                 if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
-                    let filesize = current_file.data.len();
                     for _bulk_action in 0..34 {
                         current_file.cursor.up();
                     }
@@ -182,35 +482,82 @@ fn write_mode(events: &Events, app: &mut App, terminal: &mut Term) -> Result<(),
                 if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
                     let filesize = current_file.data.len();
                     for _bulk_action in 0..34 {
-                        current_file.cursor.down(filesize);
+                        current_file.cursor.down(filesize + append);
+                    }
+                    current_file.cursor.clamp_append(filesize);
+                }
+            }
+            Key::Backspace => {
+                if let (Mode::Insert, Tab::File(current_file)) =
+                    (&app.mode, &mut app.tabs[app.tabs_index])
+                {
+                    let loc = current_file.cursor.loc();
+                    if current_file.cursor.pos.0 % 2 == 1 && loc < current_file.data.len() {
+                        // Drop the half-typed byte under the cursor
+                        current_file.splice(loc, 1, &[]);
+                        current_file.cursor.goto(loc);
+                    } else if loc > 0 {
+                        current_file.splice(loc - 1, 1, &[]);
+                        current_file.cursor.goto(loc - 1);
                     }
                 }
             }
-            Key::Char(c) => {
-                if c.is_ascii_hexdigit() {
-                    if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
-                        let digit = u8::from_str_radix(&c.to_string()[..], 16)?;
-                        let cursor_pos = current_file.cursor.pos;
-                        let byte_pos = (cursor_pos.0 / 2) + (cursor_pos.1 * 0x10);
-                        match app.mode {
-                            Mode::Insert => {
-                                //TODO: Implement insert
+            Key::Delete => {
+                if let (Mode::Insert, Tab::File(current_file)) =
+                    (&app.mode, &mut app.tabs[app.tabs_index])
+                {
+                    let loc = current_file.cursor.loc();
+                    if loc < current_file.data.len() {
+                        current_file.splice(loc, 1, &[]);
+                        current_file.cursor.goto(loc);
+                    }
+                }
+            }
+            Key::Char(c) if c.is_ascii_hexdigit() => {
+                if let Tab::File(current_file) = &mut app.tabs[app.tabs_index] {
+                    let digit = u8::from_str_radix(&c.to_string()[..], 16)?;
+                    let cursor_pos = current_file.cursor.pos;
+                    let byte_pos = current_file.cursor.loc();
+                    match app.mode {
+                        Mode::Insert => {
+                            if cursor_pos.0 % 2 == 0 || byte_pos >= current_file.data.len() {
+                                // Upper 4 bits start a new byte, shifting the rest of the file
+                                current_file.splice(byte_pos, 0, &[(digit << 4) & 0xF0]);
+                                current_file.cursor.goto(byte_pos);
+                                current_file.cursor.pos.0 += 1;
+                            } else {
+                                // Lower 4 bits finish the byte, move on to the next one
+                                let byte = match current_file.read_range(byte_pos..byte_pos + 1) {
+                                    Ok(data) => (data[0] & 0xF0) | (digit & 0xF),
+                                    Err(e) => {
+                                        app.error(e);
+                                        return Ok(());
+                                    }
+                                };
+                                current_file.set_byte(byte_pos, byte);
+                                current_file.cursor.goto(byte_pos + 1);
                             }
-                            Mode::Replace => {
-                                if cursor_pos.0 % 2 == 0 {
-                                    // modify upper 4 bits
-                                    current_file.data[byte_pos] =
-                                        (current_file.data[byte_pos] & 0xF) | ((digit << 4) & 0xF0);
-                                } else {
-                                    // lower 4 bits
-                                    current_file.data[byte_pos] =
-                                        (current_file.data[byte_pos] & 0xF0) | (digit & 0xF);
+                        }
+                        Mode::Replace if byte_pos < current_file.data.len() => {
+                            let byte = match current_file.read_range(byte_pos..byte_pos + 1) {
+                                Ok(data) => data[0],
+                                Err(e) => {
+                                    app.error(e);
+                                    return Ok(());
                                 }
-                                let filesize = current_file.data.len();
-                                current_file.cursor.right(filesize);
+                            };
+                            if cursor_pos.0 % 2 == 0 {
+                                // modify upper 4 bits
+                                current_file
+                                    .set_byte(byte_pos, (byte & 0xF) | ((digit << 4) & 0xF0));
+                            } else {
+                                // lower 4 bits
+                                current_file.set_byte(byte_pos, (byte & 0xF0) | (digit & 0xF));
                             }
-                            _ => {}
+                            let filesize = current_file.data.len();
+                            current_file.cursor.right(filesize);
                         }
+                        _ => {}
                     }
                 }
             }
@@ -221,43 +568,148 @@ fn write_mode(events: &Events, app: &mut App, terminal: &mut Term) -> Result<(),
 }
 
 #[allow(unused_variables)]
-fn title_mode(events: &Events, app: &mut App, terminal: &mut Term) -> Result<(), failure::Error> {
-    if let Event::Input(Key::Char(':')) = events.next()? {
+fn title_mode(event: Event<Key>, app: &mut App, terminal: &mut Term) -> Result<(), failure::Error> {
+    if let Event::Input(Key::Char(':')) = event {
         app.mode = Mode::TitleCommand;
         app.command = String::from(":");
     }
     Ok(())
 }
 
+fn dump_file(args: &DumpArgs) -> io::Result<()> {
+    let data = Buffer::open(tilde_expand(&args.file).unwrap_or_default())?;
+    let end = match args.length {
+        Some(length) => args.offset.saturating_add(length),
+        None => data.len(),
+    };
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    dump::dump(
+        &data,
+        args.offset..end,
+        args.format,
+        args.cols,
+        &args.file,
+        &mut out,
+    )?;
+    out.flush()
+}
+
+fn revert_dump(dump: &str, output: Option<&str>) -> Result<(), String> {
+    let text = fs::read(tilde_expand(dump).unwrap_or_default())
+        .map_err(|e| format!("{}: {}", dump, describe_io_error(&e)))?;
+    let chunks = dump::parse_dump(&String::from_utf8_lossy(&text))
+        .map_err(|e| format!("{}: {}", dump, e))?;
+    let written = match output {
+        Some(output) => fs::File::create(tilde_expand(output).unwrap_or_default())
+            .and_then(|mut file| dump::write_sparse(&chunks, &mut file)),
+        None => dump::write_stream(&chunks, &mut io::stdout().lock()),
+    };
+    written.map_err(|e| format!("{}: {}", output.unwrap_or("stdout"), describe_io_error(&e)))
+}
+
 fn main() -> Result<(), failure::Error> {
+    // Parse arguments before entering raw mode so errors print normally
+    let args = match args::parse(env::args().skip(1)) {
+        Ok(Action::Edit(args)) => args,
+        Ok(Action::Dump(args)) => {
+            if let Err(e) = dump_file(&args) {
+                // Stopping early because stdout was closed, e.g. by `head`, is fine
+                if e.kind() != io::ErrorKind::BrokenPipe {
+                    eprintln!("nail: {}: {}", args.file, describe_io_error(&e));
+                    process::exit(1);
+                }
+            }
+            return Ok(());
+        }
+        Ok(Action::Revert { dump, output }) => {
+            if let Err(e) = revert_dump(&dump, output.as_deref()) {
+                eprintln!("nail: {}", e);
+                process::exit(1);
+            }
+            return Ok(());
+        }
+        Ok(Action::Help) => {
+            println!("{}", args::USAGE);
+            return Ok(());
+        }
+        Ok(Action::Version) => {
+            println!("nail {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        Err(e) => {
+            eprintln!("nail: {}", e);
+            eprintln!("Try 'nail --help' for more information.");
+            process::exit(2);
+        }
+    };
+
+    // App
+    let mut app = App::new();
+
+    // Scripts don't depend on the user's configuration
+    if args.script.is_none() {
+        match Theme::load_user() {
+            Some(Ok(theme)) => app.set_theme(theme),
+            Some(Err(e)) => app.error(format!("Error in theme {}", e)),
+            None => {}
+        }
+        rc::load(&mut app);
+    }
+
+    if let Some(big_endian) = args.big_endian {
+        app.options.big_endian = big_endian;
+    }
+    if let Some(cols) = args.cols {
+        app.options.cols = cols;
+    }
+
+    // Load files from args
+    for file in &args.files {
+        match app.open(file, args.read_only) {
+            Ok(()) => app.mode = Mode::Default,
+            Err(e) => {
+                let error = format!("E484: Can't open file {}: {}", file, describe_io_error(&e));
+                if args.script.is_some() {
+                    eprintln!("nail: {}", error);
+                    process::exit(1);
+                }
+                app.error(error);
+            }
+        }
+    }
+
+    // Scripts run without ever touching the terminal
+    if let Some(script) = &args.script {
+        let result = args
+            .commands
+            .iter()
+            .try_for_each(|command| {
+                script::run_command(&mut app, command.clone())
+                    .map_err(|e| format!("{}: {}", command, e))
+            })
+            .and_then(|()| script::run_file(&mut app, script));
+        if let Err(e) = result {
+            eprintln!("nail: {}", e);
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
     // Terminal initialization
     let stdout = io::stdout().into_raw_mode()?;
     let stdout = MouseTerminal::from(stdout);
     let stdout = AlternateScreen::from(stdout);
     let backend = TermionBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
-    // App
-    let mut app = App {
-        tabs: Vec::new(),
-        mode: Mode::Title,
-        command: String::new(),
-        size: Rect::new(0, 0, 0, 0),
-        tabs_index: 0,
-        line_count: 0,
-        options: AppOptions::new(),
-    };
 
-    // Load files from args
-    for arg in env::args().skip(1) {
-        if app.open(&arg[..]).is_ok() {
-            app.mode = Mode::Default;
+    for command in args.commands {
+        app.command = command;
+        if let Err(e) = command_handler::handle_command(&mut app) {
+            app.error(e);
         }
     }
 
-    if app.tabs.is_empty() {
-        app.tabs.push(Tab::Title);
-    }
-
     let events = Events::new();
     let mut editor_rect = Rect::new(0, 0, 0, 0);
 
@@ -280,18 +732,8 @@ fn main() -> Result<(), failure::Error> {
             Mode::Bash => {
                 terminal.clear()?;
                 write!(terminal.backend_mut(), "{}", Goto(1, 1))?;
-                let _output = if cfg!(target_os = "windows") {
-                    Command::new("cmd")
-                        .args(&["/C", &app.command[..]])
-                        .output()
-                        .expect("failed to execute process")
-                } else {
-                    Command::new("sh")
-                        .arg("-c")
-                        .arg(&app.command[..])
-                        .output()
-                        .expect("failed to execute process")
-                };
+                command_handler::run_shell(&mut app);
+                continue;
             }
             //            Mode::Title | Mode::TitleCommand => {
             //                terminal.draw(|mut f| {
@@ -331,56 +773,69 @@ fn main() -> Result<(), failure::Error> {
                     let reserved_lines = if app.options.type_inspector { 3 } else { 0 };
                     app.line_count = (chunks[1].height - (3 + reserved_lines)) as usize;
 
+                    if app.options.auto_cols {
+                        let cols = app.fit_cols(chunks[1].width as usize);
+                        if cols != app.options.cols {
+                            app.set_cols(cols);
+                        }
+                    }
+
                     // If cursor is out of bounds, scroll
                     if let Tab::File(file) = &mut app.tabs[app.tabs_index] {
-                        if file.cursor.pos.1 * 0x10 < file.scroll_y {
-                            file.scroll_y = file.cursor.pos.1 * 0x10;
+                        let cols = file.cursor.cols;
+                        if file.cursor.pos.1 * cols < file.scroll_y {
+                            file.scroll_y = file.cursor.pos.1 * cols;
                         }
 
                         // +0 = +1 for "one past the end" -1 for "including the header line"
-                        if (file.scroll_y / 0x10) + app.line_count <= file.cursor.pos.1 {
-                            file.scroll_y = (file.cursor.pos.1 + 1 - app.line_count) * 0x10;
+                        if (file.scroll_y / cols) + app.line_count <= file.cursor.pos.1 {
+                            file.scroll_y = (file.cursor.pos.1 + 1 - app.line_count) * cols;
                         }
                     }
 
                     editor_rect = chunks[1];
 
-                    let block = Block::default().style(Style::default().bg(match app.mode {
-                        Mode::Command => Color::Red,
-                        _ => Color::DarkGray,
-                    }));
+                    let background = match app.mode {
+                        Mode::Command => app.theme.command_background,
+                        _ => app.theme.background,
+                    };
+                    let block = Block::default().style(background);
                     f.render_widget(block, app.size);
                     let index = app.tabs_index;
-                    let tabs =
-                        Tabs::new(app.tab_titles().iter().cloned().map(Spans::from).collect())
-                            .block(Block::default().borders(Borders::ALL).title("Tabs"))
-                            .select(index)
-                            .style(Style::default().fg(Color::LightBlue))
-                            .highlight_style(Style::default().fg(Color::Red));
+                    let tabs = Tabs::new(app.tab_titles().into_iter().map(Spans::from).collect())
+                        .block(Block::default().borders(Borders::ALL).title("Tabs"))
+                        .select(index)
+                        .style(app.theme.tabs)
+                        .highlight_style(app.theme.tab_selected);
                     f.render_widget(tabs, chunks[0]);
                     let view = app.current_tab().view(&app);
                     let p = Paragraph::new(view).block(
                         Block::default()
                             .title(app.current_tab().long_title())
                             .borders(Borders::ALL)
-                            .border_style(Style::default().fg(match app.mode {
-                                Mode::Insert | Mode::Replace => Color::Yellow,
-                                _ => Color::White,
-                            })),
+                            .border_style(match app.mode {
+                                Mode::Insert | Mode::Replace => app.theme.border_insert,
+                                _ => app.theme.border,
+                            }),
                     );
                     f.render_widget(p, chunks[1]);
-                    let p = Paragraph::new(vec![Spans::from(Span::raw(app.command.clone()))])
-                        .style(Style::default().bg(match app.mode {
-                            Mode::Command => Color::Red,
-                            _ => Color::DarkGray,
-                        }));
+                    let command_line = match (&app.mode, &app.message) {
+                        (Mode::Command, _) | (Mode::TitleCommand, _) | (_, None) => {
+                            Span::raw(app.command.clone())
+                        }
+                        (_, Some(Message::Info(text))) => Span::raw(text.clone()),
+                        (_, Some(Message::Error(text))) => {
+                            Span::styled(text.clone(), app.theme.error)
+                        }
+                    };
+                    let p = Paragraph::new(vec![Spans::from(command_line)]).style(background);
                     f.render_widget(p, chunks[2]);
                 })?;
             }
         }
 
         match app.mode {
-            Mode::Default | Mode::Insert | Mode::Replace => {
+            Mode::Default | Mode::Insert | Mode::Replace | Mode::Visual | Mode::VisualLine => {
                 terminal.show_cursor()?;
                 editor_rect.x = 0;
                 if let Tab::File(file) = &mut app.tabs[app.tabs_index] {
@@ -390,10 +845,11 @@ fn main() -> Result<(), failure::Error> {
                         Goto(
                             (editor_rect.x as usize
                                 + 11
-                                + ((file.cursor.pos.0 / 2) * 3)
+                                + dump::hex_column(file.cursor.pos.0 / 2, app.options.group)
                                 + (file.cursor.pos.0 % 2)) as u16,
                             (editor_rect.y as usize + 3 + file.cursor.pos.1
-                                - (file.scroll_y / 0x10)) as u16
+                                - (file.scroll_y / file.cursor.cols))
+                                as u16
                         )
                     )?;
                 }
@@ -402,12 +858,34 @@ fn main() -> Result<(), failure::Error> {
             _ => terminal.hide_cursor()?,
         }
 
+        if let Mode::Quit = app.mode {
+            break;
+        }
+        // Keys produced by a mapping are never mapped again
+        let event = match app.typeahead.pop_front() {
+            Some(key) => Event::Input(key),
+            None => match events.next()? {
+                Event::Input(key) => match mapping(&app, key) {
+                    Some(keys) => {
+                        app.typeahead.extend(keys);
+                        continue;
+                    }
+                    None => Event::Input(key),
+                },
+                event => event,
+            },
+        };
+        match event {
+            // Messages only last until the next keypress
+            Event::Input(_) => app.message = None,
+            Event::Tick => app.check_time(false),
+        }
         match app.mode {
-            Mode::Default => default_mode(&events, &mut app, &mut terminal)?,
-            Mode::Command | Mode::TitleCommand => command_mode(&events, &mut app, &mut terminal)?,
-            Mode::Insert | Mode::Replace => write_mode(&events, &mut app, &mut terminal)?,
-            Mode::Title => title_mode(&events, &mut app, &mut terminal)?,
-            Mode::Quit => break,
+            Mode::Default => default_mode(event, &mut app, &mut terminal)?,
+            Mode::Command | Mode::TitleCommand => command_mode(event, &mut app, &mut terminal)?,
+            Mode::Insert | Mode::Replace => write_mode(event, &mut app, &mut terminal)?,
+            Mode::Visual | Mode::VisualLine => visual_mode(event, &mut app, &mut terminal)?,
+            Mode::Title => title_mode(event, &mut app, &mut terminal)?,
             _ => {}
         };
     }
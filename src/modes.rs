@@ -5,6 +5,8 @@ pub enum Mode {
     Command,
     Insert,
     Replace,
+    Visual,
+    VisualLine,
     Bash,
     Quit,
 }
@@ -1,6 +1,7 @@
-use tui::style::{Color, Style};
 use tui::text::{Span, Spans};
 
+use crate::theme::Theme;
+
 static OPEN_TEXT: &str = r#"
                      `7MN.   `7MF'     db      `7MMF'`7MMF'
                        MMN.    M      ;MM:       MM    MM
@@ -29,12 +30,46 @@ static OPEN_TEXT: &str = r#"
 static HELP_TEXT: &str = r#"Commands:
 ---------
 ^:e [file]^ - open [file] as new buffer, creates a new file if it doesn't exist
-^:q^ - quit
-^:w^ [file] - write to [file], default is the path opened from
+^:view [file]^ - open [file] read-only, like starting nail with ^-R^
+^:e!^ - discard all changes and reload the current file from disk
+^:checktime^ - check whether another program changed an open file, which is also done regularly
+^:q/:qa^ - quit, refuses while a buffer has unsaved changes (marked [+]) unless given a ^!^
+^:w^ [file] - write to [file], default is the path opened from, replacing it atomically
+    refuses to overwrite a read-only file or one another program changed unless given a ^!^ (^:w!^)
+^:wq/:x^ [file] - write the current buffer and quit, ^:x^ only writes if it was modified or given [file]
+^:wa/:wqa^ - write all modified buffers, then quit for ^:wqa^
 ^:bnext/:bprev^ - next/previous "buffer" (tab)
-^:bd^ - buffer delete
+^:bd^ - buffer delete, ^:bd!^ discards unsaved changes
 ^:topen/:tclose/:ttoggle^ - open, close or toggle type inspector
 ^:0x[hex number]^ - goto offset [hex number] in the current file
+^:undo/:redo^ - undo/redo the last change
+^:earlier N/:later N^ - go back/forward N changes in time, across undo branches
+^:changes^ - list the ranges changed since the file was saved, and where bytes were deleted
+^:[range]d [x]/:[range]y [x]^ - delete/yank bytes into register [x], default is the byte under the cursor
+^:[range]fill [hex bytes]^ - fill with a repeating pattern, default is the byte under the cursor
+^:[range]export [file]^ - write bytes to [file], default is the whole buffer
+^:[range]checksum [alg]^ - sum8, sum16, xor8, crc32 (default) or adler32, default is the whole buffer
+    [range] is ^%^ (whole buffer), ^'<,'>^ (last selection) or ^addr[,addr]^ where an addr is
+    ^0x[hex]^, ^.^ (cursor) or ^$^ (last byte) inside the buffer; put a space between a hex addr
+    and the command (^:0x10 d^, as ^:0x10d^ goes to 0x10D)
+^:[range]s/[pattern]/[replacement]/[flags]^ - replace a search pattern with hex bytes, a "string"
+    or a typed value, default range is the current row, ^g^ flag replaces every match instead of
+    the first, ^r^ flag allows a replacement of a different length (e.g. :%s/"v1.0"/"v1.1"/g)
+^:set be/le^ - read and search multi-byte values as big/little endian
+^:set readonly/noreadonly^ - refuse (or allow again) changes to the current buffer, marked [RO]
+^:set cols=N^ - show N bytes per row (up to 256), ^cols=auto^ fits as many as the window has room for
+^:set group=N^ - show bytes in groups of 1, 2, 4 or 8 without spaces inside a group
+^:set color=none/class/gradient^ - color bytes by kind (zero, 0xFF, printable, whitespace, control, high) or by value
+^:map [key] [keys]^ - make a key typed in normal or visual mode stand for other keys (e.g. :map Q :q<CR>),
+    without keys show the mappings, ^:unmap [key]^ removes one
+^:colorscheme [name]^ - switch to the default, high-contrast or mono colors, or ^user^ to reload
+    ~/.config/nail/theme.toml, which is read on startup
+^:set backup/nobackup^ - keep the previous version of a written file as [file]~
+^:set inplace/noinplace^ - when the size is unchanged, ^:w^ only writes the modified bytes into the
+    file it was opened from, which is instant for huge files but not atomic and keeps no backup
+^:read-dump [file]^ - write the bytes of a hex dump (nail's or xxd's layout or bare hex) over the
+    buffer at the offsets it lists, e.g. one made by ^nail --dump^ and edited elsewhere
+^:noh^ - stop highlighting search matches until the next search
 ^:help^ - open help menu
 
 Keybinds:
@@ -43,16 +78,33 @@ Keybinds:
 ^shift+r^ - enter replace mode (from default mode)
 ^w/b^ - move forwards/backwards to nearest 4-byte boundary
 ^shift+g^ - jump to bottom of buffer
+^v/shift+v^ - select bytes/whole rows (visual mode)
+^y/d/o^ - yank/delete the selection, jump to its other end (visual mode)
+^:^ - run a command on the selection (visual mode)
+^"[x]^ - use register [x] (a-z, A-Z to append, 0-9, " or _) for the next yank, delete or put
+^x^ - delete the byte under the cursor
+^p/shift+p^ - put a register over the bytes at the cursor/insert it before the cursor
+^ctrl+r [x]^ - put register [x] at the cursor (insert and replace mode)
+^u/ctrl+r^ - undo/redo, everything typed in one insert/replace session is one step
+^]c/[c^ - jump to the next/previous change since the file was saved, changed bytes and the ones after a deletion are underlined
 ^:^ - enter command mode
-^i^ - enter insert mode (WIP)
-^/^ - enter command mode (for search)
+^i^ - enter insert mode, typed hex digits insert new bytes at the cursor
+^backspace/delete^ - remove the byte before/under the cursor (insert mode)
+^/[hex bytes]^ - search for a byte pattern, ^??^ or ^?F^ match any byte or nibble (e.g. /DE AD ?? EF)
+^/"text"^ - search for a string, ^/utf16le:"text"^ and ^/utf16be:"text"^ search for it as UTF-16,
+    add an ^i^ after the closing quote to ignore case (e.g. /"header"i)
+^/re:[regex]^ - search with a regex over the raw bytes (e.g. /re:\x7FELF)
+^/[type]:[value]^ - search for a u8/i8/u16/i16/u32/i32/u64/i64/f32/f64 in the current endianness,
+    add ^le^/^be^ to the type to pick one (e.g. /u32be:1337), floats match values a few ULPs
+    from the typed one unless a wider tolerance is given (e.g. /f32:0.1~0.0001)
+^n/shift+n^ - jump to the next/previous match, wrapping around the ends of the buffer
 "#;
 
 pub fn get_title_view() -> Vec<Spans<'static>> {
     vec![Spans::from(Span::raw(OPEN_TEXT))]
 }
 
-pub fn get_help_view() -> Vec<Spans<'static>> {
+pub fn get_help_view(theme: &Theme) -> Vec<Spans<'static>> {
     HELP_TEXT
         .split('^')
         .enumerate()
@@ -60,7 +112,7 @@ pub fn get_help_view() -> Vec<Spans<'static>> {
             if i % 2 == 0 {
                 Spans::from(Span::raw(text))
             } else {
-                Spans::from(Span::styled(text, Style::default().fg(Color::Red)))
+                Spans::from(Span::styled(text, theme.help_key))
             }
         })
         .collect()
@@ -0,0 +1,94 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+use crate::app::App;
+use crate::command_handler::describe_io_error;
+use crate::script;
+use crate::tilde_expand::tilde_expand;
+
+pub const RC_PATH: &str = "~/.config/nail/nailrc";
+/// Copies of the `.nailrc` files the user chose to always run, named after their
+/// path with `/` replaced by `%` like vim's undo files
+const TRUSTED_DIR: &str = "~/.config/nail/trust";
+const LOCAL_RC: &str = ".nailrc";
+
+/// Run the startup file, `$NAILRC` or `RC_PATH`, then a `.nailrc` in the current
+/// directory if the user trusts it. The first error is shown in the status line.
+pub fn load(app: &mut App) {
+    let mut errors = Vec::new();
+    let (path, required) = match env::var("NAILRC").ok().filter(|p| !p.is_empty()) {
+        Some(path) => (path, true),
+        None => (String::from(RC_PATH), false),
+    };
+    let path = tilde_expand(&path).unwrap_or(path);
+    match fs::read_to_string(&path) {
+        Ok(text) => errors.extend(script::run_startup(app, &path, &text)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => {}
+        Err(e) => errors.push(format!("{}: {}", path, describe_io_error(&e))),
+    }
+
+    if let Ok(text) = fs::read_to_string(LOCAL_RC) {
+        let is_global = match (fs::canonicalize(LOCAL_RC), fs::canonicalize(&path)) {
+            (Ok(local), Ok(global)) => local == global,
+            _ => false,
+        };
+        if !is_global && trusted(&text, &mut errors) {
+            errors.extend(script::run_startup(app, LOCAL_RC, &text));
+        }
+    }
+
+    match &errors[..] {
+        [] => {}
+        [error] => app.error(error.clone()),
+        [error, rest @ ..] => app.error(format!("{} ({} more)", error, rest.len())),
+    }
+}
+
+/// Whether the `.nailrc` holding `text` may run. The user is asked unless they
+/// chose to always run it before and it hasn't changed since.
+fn trusted(text: &str, errors: &mut Vec<String>) -> bool {
+    let path = match fs::canonicalize(LOCAL_RC) {
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(_) => return false,
+    };
+    // The whole file is compared, so no other contents can pass for the trusted ones
+    let trusted_path = Path::new(&tilde_expand(TRUSTED_DIR).unwrap_or_default())
+        .join(path.replace('/', "%"))
+        .to_string_lossy()
+        .into_owned();
+    if fs::read_to_string(&trusted_path).is_ok_and(|trusted| trusted == text) {
+        return true;
+    }
+
+    if !termion::is_tty(&io::stdin()) {
+        return false;
+    }
+    eprint!(
+        "nail: {} can run any command, including shell commands. Run it? [y]es/[N]o/[a]lways: ",
+        path
+    );
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    match answer.trim() {
+        "y" | "yes" => true,
+        "a" | "always" => {
+            if let Err(e) = write_trusted(&trusted_path, text) {
+                errors.push(format!("{}: {}", trusted_path, describe_io_error(&e)));
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+fn write_trusted(path: &str, text: &str) -> io::Result<()> {
+    if let Some(dir) = Path::new(path).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::File::create(path)?.write_all(text.as_bytes())
+}
@@ -0,0 +1,77 @@
+use std::collections::{HashMap, VecDeque};
+
+const DELETE_RING_LEN: usize = 9;
+
+/// Vim style registers holding raw bytes. They live on `App` so they can be
+/// used to copy data between tabs.
+pub struct Registers {
+    unnamed: Vec<u8>,
+    yanked: Vec<u8>,
+    deleted: VecDeque<Vec<u8>>,
+    named: HashMap<char, Vec<u8>>,
+}
+
+impl Registers {
+    pub fn new() -> Registers {
+        Registers {
+            unnamed: Vec::new(),
+            yanked: Vec::new(),
+            deleted: VecDeque::new(),
+            named: HashMap::new(),
+        }
+    }
+
+    /// Whether `name` can follow `"` to select a register
+    pub fn is_valid(name: char) -> bool {
+        name == '"' || name == '_' || name.is_ascii_digit() || name.is_ascii_alphabetic()
+    }
+
+    /// Store yanked bytes, in `"0` unless a register was given
+    pub fn yank(&mut self, register: Option<char>, data: Vec<u8>) {
+        match register {
+            Some('_') => {}
+            Some(name) if name.is_ascii_alphabetic() => self.store_named(name, data),
+            _ => {
+                self.yanked = data.clone();
+                self.unnamed = data;
+            }
+        }
+    }
+
+    /// Store deleted bytes, shifting the numbered `"1`-`"9` ring unless a register was given
+    pub fn delete(&mut self, register: Option<char>, data: Vec<u8>) {
+        match register {
+            Some('_') => {}
+            Some(name) if name.is_ascii_alphabetic() => self.store_named(name, data),
+            _ => {
+                self.deleted.push_front(data.clone());
+                self.deleted.truncate(DELETE_RING_LEN);
+                self.unnamed = data;
+            }
+        }
+    }
+
+    /// Uppercase names append to the lowercase register, like in vim
+    fn store_named(&mut self, name: char, data: Vec<u8>) {
+        let register = self.named.entry(name.to_ascii_lowercase()).or_default();
+        if name.is_ascii_uppercase() {
+            register.extend_from_slice(&data);
+        } else {
+            *register = data;
+        }
+        self.unnamed = register.clone();
+    }
+
+    pub fn get(&self, register: Option<char>) -> Option<&[u8]> {
+        let data = match register {
+            None | Some('"') => &self.unnamed,
+            Some('0') => &self.yanked,
+            Some(name @ '1'..='9') => self.deleted.get(name as usize - '1' as usize)?,
+            Some(name) if name.is_ascii_alphabetic() => {
+                self.named.get(&name.to_ascii_lowercase())?
+            }
+            _ => return None,
+        };
+        Some(&data[..])
+    }
+}
@@ -0,0 +1,73 @@
+use std::fs;
+
+use crate::app::{App, Message};
+use crate::command_handler::{self, describe_io_error};
+use crate::modes::Mode;
+use crate::tilde_expand::tilde_expand;
+
+/// Turn a script line into a command line. The leading `:` is optional, and
+/// blank lines and comments starting with `"` or `#` are skipped.
+fn command_line(line: &str) -> Option<String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('"') || line.starts_with('#') {
+        None
+    } else if line.starts_with(':') || line.starts_with('/') {
+        Some(line.to_string())
+    } else {
+        Some(format!(":{}", line))
+    }
+}
+
+/// Run a single command line without a terminal, returning the message it
+/// left. Errors, including ones only reported in the status line, are returned
+/// as `Err`.
+fn execute(app: &mut App, command: String) -> Result<Option<String>, String> {
+    app.command = command;
+    command_handler::handle_command(app)?;
+    if let Mode::Bash = app.mode {
+        command_handler::run_shell(app);
+    }
+    match app.message.take() {
+        Some(Message::Info(text)) if !text.is_empty() => Ok(Some(text)),
+        Some(Message::Error(text)) => Err(text),
+        _ => Ok(None),
+    }
+}
+
+/// Run a single command line without a terminal, printing its message
+pub fn run_command(app: &mut App, command: String) -> Result<(), String> {
+    if let Some(text) = execute(app, command)? {
+        println!("{}", text);
+    }
+    Ok(())
+}
+
+/// Run every line of the script at `path`, stopping at the first error or when
+/// a command quits
+pub fn run_file(app: &mut App, path: &str) -> Result<(), String> {
+    let text = fs::read_to_string(tilde_expand(path).unwrap_or_default())
+        .map_err(|e| format!("{}: {}", path, describe_io_error(&e)))?;
+    for (i, line) in text.lines().enumerate() {
+        if let Mode::Quit = app.mode {
+            break;
+        }
+        if let Some(command) = command_line(line) {
+            run_command(app, command).map_err(|e| format!("{}:{}: {}", path, i + 1, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Run the lines of a startup file, carrying on past errors like vim does with
+/// a vimrc. Messages are dropped and errors returned as `path:line: error`.
+pub fn run_startup(app: &mut App, path: &str, text: &str) -> Vec<String> {
+    let mut errors = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if let Some(command) = command_line(line) {
+            if let Err(e) = execute(app, command) {
+                errors.push(format!("{}:{}: {}", path, i + 1, e));
+            }
+        }
+    }
+    errors
+}
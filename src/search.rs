@@ -1,5 +1,650 @@
-use regex;
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use regex::bytes::{Regex, RegexBuilder};
+use regex_syntax::hir::{Class, Hir, HirKind, Literal, RepetitionKind, RepetitionRange};
+use regex_syntax::ParserBuilder;
+use std::ops::Range;
 
-fn parse_search_query(query: &str) -> Option<Vec<&str>> {
-    
+use crate::app::App;
+use crate::buffer::Buffer;
+use crate::command_handler::{describe_io_error, CommandResult};
+use crate::file::File as NailFile;
+use crate::tabs::Tab;
+
+const CHUNK_SIZE: usize = 0x10000;
+// Longer regex matches are cut off, and ones of patterns without a length limit
+// that only match in more than this are missed
+const REGEX_MAX_LEN: usize = 0x100000;
+// Bytes read around a chunk so anchors and word boundaries see their neighbours
+const REGEX_CONTEXT: usize = 0x10;
+// Steps between neighbouring floats a float search allows without a tolerance
+const FLOAT_ULPS: f64 = 4.0;
+
+/// One byte of a search pattern, bits cleared in `mask` match anything
+#[derive(Clone, Copy)]
+pub struct PatternByte {
+    pub value: u8,
+    pub mask: u8,
+}
+
+impl PatternByte {
+    fn exact(value: u8) -> PatternByte {
+        PatternByte { value, mask: 0xFF }
+    }
+
+    /// Matches both cases of ASCII letters by ignoring the case bit
+    fn ignore_case(value: u8) -> PatternByte {
+        if value.is_ascii_alphabetic() {
+            PatternByte {
+                value: value.to_ascii_uppercase(),
+                mask: 0xDF,
+            }
+        } else {
+            PatternByte::exact(value)
+        }
+    }
+
+    fn matches(&self, byte: u8) -> bool {
+        byte & self.mask == self.value
+    }
+}
+
+pub enum Query {
+    Bytes(Vec<PatternByte>),
+    /// A regex whose matches are at most `max_len` bytes
+    Regex {
+        regex: Regex,
+        max_len: usize,
+    },
+    /// An f32 (`width` 4) or f64 (`width` 8) within `min..=max` of the typed `value`
+    Float {
+        width: usize,
+        big_endian: bool,
+        value: f64,
+        min: f64,
+        max: f64,
+    },
+}
+
+#[derive(Clone, Copy)]
+enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+pub struct Search {
+    pub text: String,
+    pub query: Query,
+}
+
+/// Parse a search query:
+/// * `DE AD ?? EF` hex bytes, where `?` matches any nibble
+/// * `"text"` text, matched as UTF-8 (so plain ASCII)
+/// * `utf16le:"text"`/`utf16be:"text"` text encoded as UTF-16
+/// * `re:pattern` a regex over the raw bytes, matching bytes rather than UTF-8
+///
+/// * `u32:1337`, `i16:-2`, `f32:1.5` a value of that type, in `big_endian` unless
+///   the type has an `le`/`be` suffix (e.g. `u32be:1337`)
+///
+/// Text can be followed by `i` to ignore the case of ASCII letters.
+pub fn parse_search_query(query: &str, big_endian: bool) -> Result<Query, String> {
+    if let Some(result) = parse_typed_value(query, big_endian) {
+        result
+    } else if let Some(pattern) = query.strip_prefix("re:") {
+        // Unicode is disabled so `.` and `\xFF` match single raw bytes
+        let regex = RegexBuilder::new(pattern)
+            .unicode(false)
+            .build()
+            .map_err(|e| e.to_string())?;
+        let max_len = ParserBuilder::new()
+            .unicode(false)
+            .allow_invalid_utf8(true)
+            .build()
+            .parse(pattern)
+            .ok()
+            .and_then(|hir| hir_max_len(&hir))
+            .map_or(REGEX_MAX_LEN, |len| usize::min(len, REGEX_MAX_LEN));
+        Ok(Query::Regex { regex, max_len })
+    } else if let Some(text) = query.strip_prefix("utf16le:") {
+        parse_text(text, Encoding::Utf16Le)
+    } else if let Some(text) = query.strip_prefix("utf16be:") {
+        parse_text(text, Encoding::Utf16Be)
+    } else if query.starts_with('"') {
+        parse_text(query, Encoding::Utf8)
+    } else {
+        parse_hex_pattern(query).ok_or_else(|| query.to_string())
+    }
+}
+
+/// Longest match of a parsed regex, None if it has no limit
+fn hir_max_len(hir: &Hir) -> Option<usize> {
+    match hir.kind() {
+        HirKind::Empty | HirKind::Anchor(_) | HirKind::WordBoundary(_) => Some(0),
+        HirKind::Literal(Literal::Unicode(c)) => Some(c.len_utf8()),
+        HirKind::Literal(Literal::Byte(_)) | HirKind::Class(Class::Bytes(_)) => Some(1),
+        HirKind::Class(Class::Unicode(class)) => Some(
+            class
+                .ranges()
+                .last()
+                .map_or(0, |range| range.end().len_utf8()),
+        ),
+        HirKind::Repetition(repetition) => {
+            let count = match repetition.kind {
+                RepetitionKind::ZeroOrOne => 1,
+                RepetitionKind::Range(RepetitionRange::Exactly(n))
+                | RepetitionKind::Range(RepetitionRange::Bounded(_, n)) => n as usize,
+                _ => return None,
+            };
+            hir_max_len(&repetition.hir)?.checked_mul(count)
+        }
+        HirKind::Group(group) => hir_max_len(&group.hir),
+        HirKind::Concat(hirs) => hirs
+            .iter()
+            .try_fold(0usize, |len, hir| len.checked_add(hir_max_len(hir)?)),
+        HirKind::Alternation(hirs) => hirs
+            .iter()
+            .try_fold(0, |len, hir| Some(usize::max(len, hir_max_len(hir)?))),
+    }
+}
+
+/// Parse `type[le|be]:value`, returning None if `query` doesn't name a type
+fn parse_typed_value(query: &str, big_endian: bool) -> Option<Result<Query, String>> {
+    let (name, value) = query.split_at(query.find(':')?);
+    let value = value[1..].trim();
+    let (name, big_endian) = if let Some(name) = name.strip_suffix("le") {
+        (name, false)
+    } else if let Some(name) = name.strip_suffix("be") {
+        (name, true)
+    } else {
+        (name, big_endian)
+    };
+    let (signed, width) = match name {
+        "u8" => (false, 1),
+        "i8" => (true, 1),
+        "u16" => (false, 2),
+        "i16" => (true, 2),
+        "u32" => (false, 4),
+        "i32" => (true, 4),
+        "u64" => (false, 8),
+        "i64" => (true, 8),
+        "f32" => return Some(parse_float(value, 4, big_endian)),
+        "f64" => return Some(parse_float(value, 8, big_endian)),
+        _ => return None,
+    };
+    Some(parse_integer(value, signed, width, big_endian))
+}
+
+fn parse_integer(
+    value: &str,
+    signed: bool,
+    width: usize,
+    big_endian: bool,
+) -> Result<Query, String> {
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value),
+    };
+    let magnitude = match digits.strip_prefix("0x") {
+        Some(hex) => i128::from_str_radix(hex, 16),
+        None => digits.parse::<i128>(),
+    }
+    .map_err(|e| format!("{}: {}", value, e))?;
+    let value = if negative { -magnitude } else { magnitude };
+
+    let bits = width as u32 * 8;
+    let (min, max) = if signed {
+        (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1)
+    } else {
+        (0, (1i128 << bits) - 1)
+    };
+    if value < min || value > max {
+        return Err(format!("{} doesn't fit in {} bytes", value, width));
+    }
+
+    // Two's complement truncated to `width` bytes
+    let mut bytes = [0; 8];
+    if big_endian {
+        BigEndian::write_uint(&mut bytes, value as u64 & (u64::MAX >> (64 - bits)), width);
+    } else {
+        LittleEndian::write_uint(&mut bytes, value as u64 & (u64::MAX >> (64 - bits)), width);
+    }
+    Ok(Query::Bytes(
+        bytes[..width]
+            .iter()
+            .cloned()
+            .map(PatternByte::exact)
+            .collect(),
+    ))
+}
+
+/// Parse `value[~tolerance]`. Without a tolerance only values within `FLOAT_ULPS`
+/// steps of the nearest one the type can store match, absorbing rounding errors.
+fn parse_float(value: &str, width: usize, big_endian: bool) -> Result<Query, String> {
+    let (value, tolerance) = match value.find('~') {
+        Some(idx) => (&value[..idx], Some(&value[idx + 1..])),
+        None => (value, None),
+    };
+    let parsed = value
+        .parse::<f64>()
+        .map_err(|e| format!("{}: {}", value, e))?;
+    // Compare against the nearest value the type can store so `~0` matches exactly
+    let parsed = if width == 4 {
+        parsed as f32 as f64
+    } else {
+        parsed
+    };
+    let tolerance = match tolerance {
+        Some(tolerance) => tolerance
+            .parse::<f64>()
+            .map_err(|e| format!("{}: {}", tolerance, e))?
+            .abs(),
+        None if !parsed.is_finite() => 0.0,
+        None => FLOAT_ULPS * ulp(parsed, width),
+    };
+    Ok(Query::Float {
+        width,
+        big_endian,
+        value: parsed,
+        min: parsed - tolerance,
+        max: parsed + tolerance,
+    })
+}
+
+/// Distance from finite `value` to the next float of `width` bytes away from zero,
+/// or towards it for the largest one
+fn ulp(value: f64, width: usize) -> f64 {
+    if width == 4 {
+        let value = (value as f32).abs();
+        match f32::from_bits(value.to_bits() + 1) {
+            next if next.is_finite() => (next - value) as f64,
+            _ => (value - f32::from_bits(value.to_bits() - 1)) as f64,
+        }
+    } else {
+        let value = value.abs();
+        match f64::from_bits(value.to_bits() + 1) {
+            next if next.is_finite() => next - value,
+            _ => value - f64::from_bits(value.to_bits() - 1),
+        }
+    }
+}
+
+fn parse_hex_pattern(query: &str) -> Option<Query> {
+    let digits = query
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<Vec<char>>();
+    if digits.is_empty() || digits.len() % 2 != 0 {
+        return None;
+    }
+    let nibble = |c: char| match c {
+        '?' => Some((0, 0)),
+        c => c.to_digit(16).map(|d| (d as u8, 0xF)),
+    };
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let (high, high_mask) = nibble(pair[0])?;
+            let (low, low_mask) = nibble(pair[1])?;
+            Some(PatternByte {
+                value: (high << 4) | low,
+                mask: (high_mask << 4) | low_mask,
+            })
+        })
+        .collect::<Option<Vec<PatternByte>>>()
+        .map(Query::Bytes)
+}
+
+/// Parse a quoted string with optional trailing flags into a byte pattern
+fn parse_text(query: &str, encoding: Encoding) -> Result<Query, String> {
+    let (text, flags) = parse_quoted(query).ok_or_else(|| query.to_string())?;
+    let ignore_case = match flags {
+        "" => false,
+        "i" => true,
+        _ => return Err(format!("unknown flags: {}", flags)),
+    };
+    if text.is_empty() {
+        return Err(String::from("empty string"));
+    }
+    let to_pattern = |byte: u8| {
+        if ignore_case {
+            PatternByte::ignore_case(byte)
+        } else {
+            PatternByte::exact(byte)
+        }
+    };
+    let pattern = match encoding {
+        Encoding::Utf8 => text.bytes().map(to_pattern).collect(),
+        Encoding::Utf16Le | Encoding::Utf16Be => text
+            .encode_utf16()
+            .flat_map(|unit| {
+                let [low, high] = unit.to_le_bytes();
+                // Only the ASCII byte of a code unit can have its case ignored
+                let (low, high) = if high == 0 {
+                    (to_pattern(low), PatternByte::exact(high))
+                } else {
+                    (PatternByte::exact(low), PatternByte::exact(high))
+                };
+                match encoding {
+                    Encoding::Utf16Be => vec![high, low],
+                    _ => vec![low, high],
+                }
+            })
+            .collect(),
+    };
+    Ok(Query::Bytes(pattern))
+}
+
+/// Split `"text"flags` into the unescaped text and the flags
+fn parse_quoted(query: &str) -> Option<(String, &str)> {
+    let mut chars = query.strip_prefix('"')?.char_indices();
+    let mut text = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((text, &query[i + 2..])),
+            '\\' => match chars.next()?.1 {
+                'n' => text.push('\n'),
+                't' => text.push('\t'),
+                'r' => text.push('\r'),
+                '0' => text.push('\0'),
+                c => text.push(c),
+            },
+            c => text.push(c),
+        }
+    }
+    None
+}
+
+impl Query {
+    /// Longest possible match, used as the overlap between chunks
+    fn max_len(&self) -> usize {
+        match self {
+            Query::Bytes(pattern) => pattern.len(),
+            Query::Regex { max_len, .. } => *max_len,
+            Query::Float { width, .. } => *width,
+        }
+    }
+
+    /// Bytes on either side of a match that decide whether it matches
+    fn context(&self) -> usize {
+        match self {
+            Query::Regex { .. } => REGEX_CONTEXT,
+            _ => 0,
+        }
+    }
+
+    /// Bytes searched at a time, so long regex matches aren't read many times over
+    fn chunk_size(&self) -> usize {
+        usize::max(CHUNK_SIZE, self.max_len())
+    }
+
+    /// First match in `haystack` starting at or after `start`
+    fn find_at(&self, haystack: &[u8], start: usize) -> Option<Range<usize>> {
+        match self {
+            Query::Bytes(pattern) => (start..(haystack.len() + 1).saturating_sub(pattern.len()))
+                .find(|&i| {
+                    pattern
+                        .iter()
+                        .zip(&haystack[i..])
+                        .all(|(p, byte)| p.matches(*byte))
+                })
+                .map(|i| i..i + pattern.len()),
+            Query::Regex { regex, .. } => regex.find_at(haystack, start).map(|m| m.range()),
+            Query::Float {
+                width,
+                big_endian,
+                min,
+                max,
+                ..
+            } => (start..(haystack.len() + 1).saturating_sub(*width))
+                .find(|&i| {
+                    let bytes = &haystack[i..i + width];
+                    let value = match (width, big_endian) {
+                        (4, false) => LittleEndian::read_f32(bytes) as f64,
+                        (4, true) => BigEndian::read_f32(bytes) as f64,
+                        (_, false) => LittleEndian::read_f64(bytes),
+                        (_, true) => BigEndian::read_f64(bytes),
+                    };
+                    *min <= value && value <= *max
+                })
+                .map(|i| i..i + width),
+        }
+    }
+}
+
+/// Read `len` bytes at `offset` to search through
+fn read(data: &Buffer, offset: usize, len: usize) -> Result<Vec<u8>, String> {
+    data.slice(offset, len)
+        .map_err(|e| format!("Can't read file: {}", describe_io_error(&e)))
+}
+
+/// Read the bytes any match starting in `range` can cover, along with the context
+/// around them, returning where they start
+fn read_window(
+    data: &Buffer,
+    query: &Query,
+    range: Range<usize>,
+) -> Result<(usize, Vec<u8>), String> {
+    let start = range.start.saturating_sub(query.context());
+    let end = range.end + query.max_len() + query.context();
+    Ok((start, read(data, start, end - start)?))
+}
+
+/// First match starting at or after `from`
+pub fn find_next(
+    data: &Buffer,
+    query: &Query,
+    from: usize,
+) -> Result<Option<Range<usize>>, String> {
+    let mut chunk_start = from;
+    while chunk_start < data.len() {
+        let chunk_end = chunk_start + query.chunk_size();
+        let (start, haystack) = read_window(data, query, chunk_start..chunk_end)?;
+        if let Some(found) = query.find_at(&haystack, chunk_start - start) {
+            if start + found.start < chunk_end {
+                return Ok(Some(start + found.start..start + found.end));
+            }
+        }
+        chunk_start = chunk_end;
+    }
+    Ok(None)
+}
+
+/// Last match starting before `before`
+pub fn find_prev(
+    data: &Buffer,
+    query: &Query,
+    before: usize,
+) -> Result<Option<Range<usize>>, String> {
+    let mut chunk_end = usize::min(before, data.len());
+    while chunk_end > 0 {
+        let chunk_start = chunk_end.saturating_sub(query.chunk_size());
+        let (start, haystack) = read_window(data, query, chunk_start..chunk_end)?;
+        let mut last = None;
+        let mut pos = chunk_start - start;
+        while let Some(found) = query.find_at(&haystack, pos) {
+            if start + found.start >= chunk_end {
+                break;
+            }
+            last = Some(start + found.start..start + found.end);
+            pos = found.start + 1;
+        }
+        if last.is_some() {
+            return Ok(last);
+        }
+        chunk_end = chunk_start;
+    }
+    Ok(None)
+}
+
+/// Bytes a search query stands for, if it has no wildcards. Floats stand for the
+/// typed value itself.
+pub fn literal_bytes(query: &Query) -> Option<Vec<u8>> {
+    match query {
+        Query::Bytes(pattern) if pattern.iter().all(|p| p.mask == 0xFF) => {
+            Some(pattern.iter().map(|p| p.value).collect())
+        }
+        Query::Float {
+            width,
+            big_endian,
+            value,
+            ..
+        } => {
+            let mut bytes = vec![0; *width];
+            match (width, big_endian) {
+                (4, false) => LittleEndian::write_f32(&mut bytes, *value as f32),
+                (4, true) => BigEndian::write_f32(&mut bytes, *value as f32),
+                (_, false) => LittleEndian::write_f64(&mut bytes, *value),
+                (_, true) => BigEndian::write_f64(&mut bytes, *value),
+            }
+            Some(bytes)
+        }
+        _ => None,
+    }
+}
+
+/// Non-overlapping matches lying within `range` in order, reading it once. Only
+/// the first one is returned unless `global` is set.
+fn matches_within(
+    data: &Buffer,
+    query: &Query,
+    range: Range<usize>,
+    global: bool,
+) -> Result<Vec<Range<usize>>, String> {
+    let mut matches = vec![];
+    let mut pos = range.start;
+    while pos < range.end {
+        let chunk_end = usize::min(pos + query.chunk_size(), range.end);
+        let (start, haystack) = read_window(data, query, pos..chunk_end)?;
+        let mut next = pos - start;
+        while let Some(found) = query.find_at(&haystack, next) {
+            let found = start + found.start..start + found.end;
+            if found.start >= chunk_end {
+                break;
+            }
+            if found.end > range.end {
+                return Ok(matches);
+            }
+            next = usize::max(found.end, found.start + 1) - start;
+            matches.push(found);
+            if !global {
+                return Ok(matches);
+            }
+        }
+        pos = usize::max(chunk_end, start + next);
+    }
+    Ok(matches)
+}
+
+/// Replace matches of `query` that lie within `range` with `replacement` as a
+/// single undo step, returning the number of replacements. Only the first match
+/// is replaced unless `global` is set, and matches of a different length than
+/// the replacement are an error unless `resize` allows shifting the rest of the file.
+pub fn substitute(
+    file: &mut NailFile,
+    query: &Query,
+    replacement: &[u8],
+    range: Range<usize>,
+    global: bool,
+    resize: bool,
+) -> Result<usize, String> {
+    let matches = matches_within(&file.data, query, range, global)?;
+    // Make sure no match needs the file to change size before touching anything
+    if !resize {
+        if let Some(found) = matches.iter().find(|m| m.len() != replacement.len()) {
+            return Err(format!(
+                "match at 0x{:X} is {} bytes but the replacement is {}, add the r flag to resize",
+                found.start,
+                found.len(),
+                replacement.len()
+            ));
+        }
+    }
+    let (start, end) = match (matches.first(), matches.last()) {
+        (Some(first), Some(last)) => (first.start, last.end),
+        _ => return Ok(0),
+    };
+
+    // Every replacement shares the same bytes in the add buffer, and the whole
+    // span is swapped in one change
+    let replacement = file.data.append_bytes(replacement);
+    let mut new = vec![];
+    let mut pos = start;
+    for found in &matches {
+        new.extend(file.data.pieces_in(pos..found.start));
+        new.extend_from_slice(&replacement);
+        pos = found.end;
+    }
+    file.replace_pieces(start, end - start, new);
+    file.history.commit();
+    Ok(matches.len())
+}
+
+/// Every non-overlapping match that overlaps `range`, used to highlight the rows on screen
+pub fn matches_in(data: &Buffer, query: &Query, range: Range<usize>) -> Vec<Range<usize>> {
+    if range.is_empty() {
+        return vec![];
+    }
+    let from = range.start.saturating_sub(query.max_len());
+    // The rows on screen show the read error themselves
+    let (start, haystack) = match read_window(data, query, from..range.end) {
+        Ok(window) => window,
+        Err(_) => return vec![],
+    };
+    let mut matches = vec![];
+    let mut pos = from - start;
+    while let Some(found) = query.find_at(&haystack, pos) {
+        if start + found.start >= range.end {
+            break;
+        }
+        if start + found.end > range.start {
+            matches.push(start + found.start..start + found.end);
+        }
+        pos = usize::max(found.end, found.start + 1);
+    }
+    matches
+}
+
+/// Move the cursor to the next (or previous) match of the last search, wrapping
+/// around the ends of the buffer
+pub fn jump_to_match(app: &mut App, forward: bool) -> CommandResult {
+    app.search_highlight = true;
+    let search = match &app.last_search {
+        Some(search) => search,
+        None => return Err(String::from("E35: No previous regular expression")),
+    };
+    let current_file = match &mut app.tabs[app.tabs_index] {
+        Tab::File(current_file) => current_file,
+        _ => return Ok(()),
+    };
+    let loc = current_file.cursor.loc();
+    let data = &current_file.data;
+    let (found, wrapped) = if forward {
+        match find_next(data, &search.query, loc + 1)? {
+            Some(offset) => (Some(offset), None),
+            None => (
+                find_next(data, &search.query, 0)?,
+                Some("search hit BOTTOM, continuing at TOP"),
+            ),
+        }
+    } else {
+        match find_prev(data, &search.query, loc)? {
+            Some(offset) => (Some(offset), None),
+            None => (
+                find_prev(data, &search.query, data.len())?,
+                Some("search hit TOP, continuing at BOTTOM"),
+            ),
+        }
+    };
+    match found {
+        Some(found) => {
+            current_file.cursor.goto(found.start);
+            let message = match wrapped {
+                Some(message) => message.to_string(),
+                None => format!("/{}", search.text),
+            };
+            app.info(message);
+            Ok(())
+        }
+        None => Err(format!("E486: Pattern not found: {}", search.text)),
+    }
 }
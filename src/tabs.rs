@@ -6,7 +6,7 @@ use tui::text::Spans;
 
 pub enum Tab {
     Title,
-    File(NailFile),
+    File(Box<NailFile>),
     Help,
 }
 
@@ -14,23 +14,34 @@ impl Tab {
     pub fn view(&self, app: &App) -> Vec<Spans<'static>> {
         match self {
             Tab::Title => get_title_view(),
-            Tab::Help => get_help_view(),
+            Tab::Help => get_help_view(&app.theme),
             Tab::File(f) => f.hex_view(app),
         }
     }
 
-    pub fn title(&self) -> &str {
+    pub fn title(&self) -> String {
         match self {
-            Tab::Title => "title",
-            Tab::Help => "help",
-            Tab::File(f) => &f.name[..],
+            Tab::Title => String::from("title"),
+            Tab::Help => String::from("help"),
+            Tab::File(f) => format!("{}{}", f.name, markers(f)),
         }
     }
 
-    pub fn long_title(&self) -> &str {
+    pub fn long_title(&self) -> String {
         match self {
-            Tab::File(f) => &f.path[..],
+            Tab::File(f) => format!("{}{}", f.path, markers(f)),
             _ => self.title(),
         }
     }
 }
+
+fn markers(file: &NailFile) -> String {
+    let mut markers = String::new();
+    if file.read_only {
+        markers.push_str(" [RO]");
+    }
+    if file.is_modified() {
+        markers.push_str(" [+]");
+    }
+    markers
+}
@@ -0,0 +1,355 @@
+use std::fs;
+use std::io;
+
+use tui::style::{Color, Modifier, Style};
+
+use crate::color::ColorScheme;
+use crate::tilde_expand::tilde_expand;
+
+pub const THEME_PATH: &str = "~/.config/nail/theme.toml";
+
+/// Styles of every part of the interface
+#[derive(Clone)]
+pub struct Theme {
+    pub name: String,
+    /// `:set color` the scheme goes with, if any
+    pub color: Option<ColorScheme>,
+    pub background: Style,
+    /// Background in command mode
+    pub command_background: Style,
+    pub tabs: Style,
+    pub tab_selected: Style,
+    pub border: Style,
+    /// Border in insert and replace mode
+    pub border_insert: Style,
+    pub error: Style,
+    /// Offset column and column header
+    pub offset: Style,
+    /// Labels of the type inspector
+    pub inspector: Style,
+    pub selection: Style,
+    pub search_match: Style,
+    /// The search match under the cursor
+    pub current_match: Style,
+    /// Added on top of the color of bytes changed since the last save
+    pub changed: Style,
+    /// Keys and commands in the help text
+    pub help_key: Style,
+    pub byte_null: Style,
+    pub byte_ff: Style,
+    pub byte_printable: Style,
+    pub byte_whitespace: Style,
+    pub byte_control: Style,
+    /// Bytes above 0x7F
+    pub byte_high: Style,
+}
+
+fn fg(color: Color) -> Style {
+    Style::default().fg(color)
+}
+
+fn modifier(modifier: Modifier) -> Style {
+    Style::default().add_modifier(modifier)
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            name: String::from("default"),
+            color: Some(ColorScheme::Class),
+            background: Style::default().bg(Color::DarkGray),
+            command_background: Style::default().bg(Color::Red),
+            tabs: fg(Color::LightBlue),
+            tab_selected: fg(Color::Red),
+            border: fg(Color::White),
+            border_insert: fg(Color::Yellow),
+            error: Style::default().fg(Color::White).bg(Color::Red),
+            offset: fg(Color::Black),
+            inspector: fg(Color::Black),
+            selection: Style::default().bg(Color::LightBlue).fg(Color::Black),
+            search_match: Style::default().bg(Color::Yellow).fg(Color::Black),
+            current_match: Style::default().bg(Color::LightRed).fg(Color::Black),
+            changed: modifier(Modifier::BOLD | Modifier::UNDERLINED),
+            help_key: fg(Color::Red),
+            byte_null: fg(Color::DarkGray),
+            byte_ff: fg(Color::Red),
+            byte_printable: fg(Color::Cyan),
+            byte_whitespace: fg(Color::Green),
+            byte_control: fg(Color::Magenta),
+            byte_high: fg(Color::Yellow),
+        }
+    }
+}
+
+impl Theme {
+    pub const NAMES: &'static [&'static str] = &["default", "high-contrast", "mono"];
+
+    /// One of the built-in schemes
+    pub fn builtin(name: &str) -> Option<Theme> {
+        match name {
+            "default" => Some(Theme::default()),
+            "high-contrast" => Some(Theme::high_contrast()),
+            "mono" => Some(Theme::mono()),
+            _ => None,
+        }
+    }
+
+    /// Bright colors on black
+    pub fn high_contrast() -> Theme {
+        let bold = Modifier::BOLD;
+        Theme {
+            name: String::from("high-contrast"),
+            color: Some(ColorScheme::Class),
+            background: Style::default().bg(Color::Black).fg(Color::White),
+            command_background: Style::default().bg(Color::Red).fg(Color::White),
+            tabs: fg(Color::White),
+            tab_selected: fg(Color::LightYellow).add_modifier(bold),
+            border: fg(Color::White),
+            border_insert: fg(Color::LightYellow).add_modifier(bold),
+            error: Style::default()
+                .fg(Color::White)
+                .bg(Color::Red)
+                .add_modifier(bold),
+            offset: fg(Color::LightCyan),
+            inspector: fg(Color::LightCyan),
+            selection: Style::default().bg(Color::White).fg(Color::Black),
+            search_match: Style::default().bg(Color::LightYellow).fg(Color::Black),
+            current_match: Style::default()
+                .bg(Color::LightRed)
+                .fg(Color::Black)
+                .add_modifier(bold),
+            changed: modifier(bold | Modifier::UNDERLINED),
+            help_key: fg(Color::LightYellow).add_modifier(bold),
+            byte_null: fg(Color::Gray),
+            byte_ff: fg(Color::LightRed),
+            byte_printable: fg(Color::LightCyan),
+            byte_whitespace: fg(Color::LightGreen),
+            byte_control: fg(Color::LightMagenta),
+            byte_high: fg(Color::LightYellow),
+        }
+    }
+
+    /// No colors at all, only bold, underlined and reversed text
+    pub fn mono() -> Theme {
+        let plain = Style::default();
+        Theme {
+            name: String::from("mono"),
+            color: Some(ColorScheme::None),
+            background: plain,
+            command_background: plain,
+            tabs: plain,
+            tab_selected: modifier(Modifier::REVERSED),
+            border: plain,
+            border_insert: modifier(Modifier::BOLD),
+            error: modifier(Modifier::REVERSED | Modifier::BOLD),
+            offset: plain,
+            inspector: plain,
+            selection: modifier(Modifier::REVERSED),
+            search_match: modifier(Modifier::UNDERLINED),
+            current_match: modifier(Modifier::REVERSED | Modifier::UNDERLINED),
+            changed: modifier(Modifier::BOLD),
+            help_key: modifier(Modifier::BOLD),
+            byte_null: plain,
+            byte_ff: plain,
+            byte_printable: plain,
+            byte_whitespace: plain,
+            byte_control: plain,
+            byte_high: plain,
+        }
+    }
+
+    fn element(&mut self, name: &str) -> Option<&mut Style> {
+        Some(match name {
+            "background" => &mut self.background,
+            "command-background" => &mut self.command_background,
+            "tabs" => &mut self.tabs,
+            "tab-selected" => &mut self.tab_selected,
+            "border" => &mut self.border,
+            "border-insert" => &mut self.border_insert,
+            "error" => &mut self.error,
+            "offset" => &mut self.offset,
+            "inspector" => &mut self.inspector,
+            "selection" => &mut self.selection,
+            "search-match" => &mut self.search_match,
+            "current-match" => &mut self.current_match,
+            "changed" => &mut self.changed,
+            "help-key" => &mut self.help_key,
+            "byte-null" => &mut self.byte_null,
+            "byte-ff" => &mut self.byte_ff,
+            "byte-printable" => &mut self.byte_printable,
+            "byte-whitespace" => &mut self.byte_whitespace,
+            "byte-control" => &mut self.byte_control,
+            "byte-high" => &mut self.byte_high,
+            _ => return None,
+        })
+    }
+
+    /// Read the user's theme from `THEME_PATH`, or `None` if there isn't one
+    pub fn load_user() -> Option<Result<Theme, String>> {
+        let path = tilde_expand(THEME_PATH)?;
+        match fs::read_to_string(&path) {
+            Ok(text) => Some(Theme::parse(&text).map_err(|e| format!("{}:{}", path, e))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => Some(Err(format!("{}: {}", path, e))),
+        }
+    }
+
+    /// Parse a theme file, a small subset of TOML:
+    ///
+    /// ```toml
+    /// scheme = "high-contrast"  # built-in scheme to start from
+    /// color = "gradient"        # `:set color` to go with it
+    ///
+    /// [selection]
+    /// fg = "black"
+    /// bg = "#87afff"            # or a number from the 256 color palette
+    /// modifiers = ["bold"]
+    /// ```
+    ///
+    /// Errors start with the line number they were found on.
+    pub fn parse(text: &str) -> Result<Theme, String> {
+        let mut theme = Theme {
+            name: String::from("user"),
+            ..Theme::default()
+        };
+        let mut section: Option<String> = None;
+        for (i, line) in text.lines().enumerate() {
+            let error = |e: String| format!("{}: {}", i + 1, e);
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let name = name.trim();
+                if theme.element(name).is_none() {
+                    return Err(error(format!("unknown element [{}]", name)));
+                }
+                section = Some(name.to_string());
+                continue;
+            }
+            let (key, value) = match line.find('=') {
+                Some(idx) => (line[..idx].trim(), line[idx + 1..].trim()),
+                None => return Err(error(format!("expected key = value: {}", line))),
+            };
+            match &section {
+                None => match key {
+                    "scheme" => {
+                        let name = parse_string(value).map_err(error)?;
+                        let base = Theme::builtin(&name)
+                            .ok_or_else(|| error(format!("unknown scheme: {}", name)))?;
+                        theme = Theme {
+                            name: theme.name,
+                            ..base
+                        };
+                    }
+                    "color" => {
+                        let name = parse_string(value).map_err(error)?;
+                        let color = ColorScheme::new(&name)
+                            .ok_or_else(|| error(format!("unknown color: {}", name)))?;
+                        theme.color = Some(color);
+                    }
+                    _ => return Err(error(format!("unknown key: {}", key))),
+                },
+                Some(name) => {
+                    let style = theme.element(name).unwrap();
+                    // Palette numbers can be given without quotes
+                    let color = || match value.parse() {
+                        Ok(index) => Ok(Color::Indexed(index)),
+                        Err(_) => parse_string(value)
+                            .and_then(|name| parse_color(&name))
+                            .map_err(error),
+                    };
+                    match key {
+                        "fg" => style.fg = Some(color()?),
+                        "bg" => style.bg = Some(color()?),
+                        "modifiers" => {
+                            style.add_modifier = parse_modifiers(value).map_err(error)?;
+                            style.sub_modifier = Modifier::empty();
+                        }
+                        _ => return Err(error(format!("unknown key: {}", key))),
+                    }
+                }
+            }
+        }
+        Ok(theme)
+    }
+}
+
+/// Drop a `#` comment, unless it's inside a string like a `"#rrggbb"` color
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_string(value: &str) -> Result<String, String> {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .filter(|v| !v.contains('"'))
+        .map(String::from)
+        .ok_or_else(|| format!("expected a quoted string: {}", value))
+}
+
+/// A color name, `"#rrggbb"` or a number from the 256 color palette
+fn parse_color(name: &str) -> Result<Color, String> {
+    let color = match name {
+        "reset" => Color::Reset,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" => Color::Gray,
+        "darkgray" => Color::DarkGray,
+        "lightred" => Color::LightRed,
+        "lightgreen" => Color::LightGreen,
+        "lightyellow" => Color::LightYellow,
+        "lightblue" => Color::LightBlue,
+        "lightmagenta" => Color::LightMagenta,
+        "lightcyan" => Color::LightCyan,
+        "white" => Color::White,
+        name => {
+            let rgb = name
+                .strip_prefix('#')
+                .filter(|hex| hex.len() == 6)
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok());
+            match (rgb, name.parse()) {
+                (Some(rgb), _) => Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8),
+                (None, Ok(index)) => Color::Indexed(index),
+                (None, Err(_)) => return Err(format!("unknown color: {}", name)),
+            }
+        }
+    };
+    Ok(color)
+}
+
+/// An array of modifier names like `["bold", "underlined"]`
+fn parse_modifiers(value: &str) -> Result<Modifier, String> {
+    let list = value
+        .strip_prefix('[')
+        .and_then(|v| v.strip_suffix(']'))
+        .ok_or_else(|| format!("expected a list of modifiers: {}", value))?;
+    let mut modifiers = Modifier::empty();
+    for name in list.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        modifiers |= match &parse_string(name)?[..] {
+            "bold" => Modifier::BOLD,
+            "dim" => Modifier::DIM,
+            "italic" => Modifier::ITALIC,
+            "underlined" => Modifier::UNDERLINED,
+            "reversed" => Modifier::REVERSED,
+            "blink" => Modifier::SLOW_BLINK,
+            "crossed-out" => Modifier::CROSSED_OUT,
+            name => return Err(format!("unknown modifier: {}", name)),
+        };
+    }
+    Ok(modifiers)
+}
@@ -0,0 +1,204 @@
+use crate::buffer::{pieces_len, Buffer, Piece};
+
+/// A single splice of the buffer: `old` was replaced by `new` starting at `offset`.
+/// Storing pieces instead of bytes keeps undoing a large deletion cheap and lets
+/// undone regions go back to referencing the original file.
+pub struct Change {
+    pub offset: usize,
+    pub old: Vec<Piece>,
+    pub new: Vec<Piece>,
+}
+
+impl Change {
+    fn apply(&self, data: &mut Buffer) {
+        data.replace(self.offset, pieces_len(&self.old), self.new.clone());
+    }
+
+    fn revert(&self, data: &mut Buffer) {
+        data.replace(self.offset, pieces_len(&self.new), self.old.clone());
+    }
+}
+
+struct UndoNode {
+    parent: usize,
+    // Child that `redo` moves to, the most recently created or undone one
+    redo_child: Option<usize>,
+    changes: Vec<Change>,
+}
+
+/// Vim style undo tree. Nodes are stored in the order they were created so a
+/// node's index doubles as its change number for `:earlier`/`:later`.
+pub struct UndoTree {
+    nodes: Vec<UndoNode>,
+    current: usize,
+    pending: Vec<Change>,
+}
+
+impl UndoTree {
+    pub fn new() -> UndoTree {
+        UndoTree {
+            nodes: vec![UndoNode {
+                parent: 0,
+                redo_child: None,
+                changes: Vec::new(),
+            }],
+            current: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Add a change that has already been applied to the buffer to the open undo step
+    pub fn record(&mut self, change: Change) {
+        self.pending.push(change);
+    }
+
+    /// Close the open undo step so that everything recorded since the last commit
+    /// is undone together
+    pub fn commit(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let idx = self.nodes.len();
+        self.nodes.push(UndoNode {
+            parent: self.current,
+            redo_child: None,
+            changes: std::mem::take(&mut self.pending),
+        });
+        self.nodes[self.current].redo_child = Some(idx);
+        self.current = idx;
+    }
+
+    /// Number of the change the buffer currently reflects, 0 being the unmodified file
+    pub fn seq(&self) -> usize {
+        self.current
+    }
+
+    /// Whether changes were recorded since the last commit
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    pub fn last_seq(&self) -> usize {
+        self.nodes.len() - 1
+    }
+
+    /// Revert the current undo step, returning the offset of the first change
+    pub fn undo(&mut self, data: &mut Buffer) -> Option<usize> {
+        self.commit();
+        if self.current == 0 {
+            return None;
+        }
+        let node = &self.nodes[self.current];
+        for change in node.changes.iter().rev() {
+            change.revert(data);
+        }
+        let offset = node.changes.first().map(|c| c.offset);
+        let parent = node.parent;
+        self.nodes[parent].redo_child = Some(self.current);
+        self.current = parent;
+        offset
+    }
+
+    /// Reapply the most recently undone step, returning the offset of the first change
+    pub fn redo(&mut self, data: &mut Buffer) -> Option<usize> {
+        self.commit();
+        let child = self.nodes[self.current].redo_child?;
+        for change in &self.nodes[child].changes {
+            change.apply(data);
+        }
+        self.current = child;
+        self.nodes[child].changes.first().map(|c| c.offset)
+    }
+
+    /// Move the buffer to the state after change `target`, walking across branches
+    /// of the tree if needed
+    pub fn goto(&mut self, data: &mut Buffer, target: usize) -> Option<usize> {
+        self.commit();
+        let target = usize::min(target, self.last_seq());
+        if target == self.current {
+            return None;
+        }
+
+        let target_path = self.path_to_root(target);
+        let mut offset = None;
+        while !target_path.contains(&self.current) {
+            offset = self.undo(data);
+        }
+
+        // Walk back down the target's branch from the common ancestor
+        let common = target_path.iter().position(|&n| n == self.current).unwrap();
+        for &node in target_path[..common].iter().rev() {
+            self.nodes[self.current].redo_child = Some(node);
+            offset = self.redo(data);
+        }
+        offset
+    }
+
+    fn path_to_root(&self, mut node: usize) -> Vec<usize> {
+        let mut path = vec![node];
+        while node != 0 {
+            node = self.nodes[node].parent;
+            path.push(node);
+        }
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn contents(data: &Buffer) -> Vec<u8> {
+        data.slice(0, usize::MAX).unwrap()
+    }
+
+    /// Make a random edit to the buffer and the model and record it, like `File::splice`
+    fn edit(data: &mut Buffer, tree: &mut UndoTree, model: &mut Vec<u8>, rng: &mut StdRng) {
+        let offset = rng.gen_range(0..=model.len());
+        let len = rng.gen_range(0..=usize::min(8, model.len() - offset));
+        let bytes = (0..rng.gen_range(0..8))
+            .map(|_| rng.gen())
+            .collect::<Vec<u8>>();
+        let new = data.append_bytes(&bytes);
+        let old = data.replace(offset, len, new.clone());
+        tree.record(Change { offset, old, new });
+        model.splice(offset..offset + len, bytes);
+    }
+
+    #[test]
+    fn random_undo_redo_and_goto_match_model() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut model = (0..200).map(|_| rng.gen()).collect::<Vec<u8>>();
+        let mut data = Buffer::from_vec(model.clone());
+        let mut tree = UndoTree::new();
+        // Contents of the buffer after each change number
+        let mut states = vec![model.clone()];
+        for _ in 0..2000 {
+            match rng.gen_range(0..4) {
+                0 => {
+                    model = states[tree.seq()].clone();
+                    for _ in 0..rng.gen_range(1..4) {
+                        edit(&mut data, &mut tree, &mut model, &mut rng);
+                    }
+                    tree.commit();
+                    assert_eq!(tree.seq(), states.len());
+                    states.push(model.clone());
+                }
+                1 => {
+                    tree.undo(&mut data);
+                }
+                2 => {
+                    tree.redo(&mut data);
+                }
+                _ => {
+                    let target = rng.gen_range(0..states.len());
+                    tree.goto(&mut data, target);
+                    assert_eq!(tree.seq(), target);
+                }
+            }
+            assert_eq!(contents(&data), states[tree.seq()]);
+        }
+    }
+}
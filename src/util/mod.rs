@@ -53,15 +53,42 @@ impl Iterator for SinSignal {
     }
 }
 
+/// Parse hex bytes such as "DE AD BE EF" or "DEADBEEF"
+pub fn parse_hex_bytes(text: &str) -> Option<Vec<u8>> {
+    let digits = text
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<Vec<char>>();
+    if digits.is_empty() || digits.len() % 2 != 0 {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(&pair.iter().collect::<String>(), 16).ok())
+        .collect()
+}
+
 const WORD_LEN: usize = 4;
 
 pub struct HexCursor {
+    /// Nibble within the row and row number
     pub pos: (usize, usize),
+    /// Bytes per row
+    pub cols: usize,
 }
 
 impl HexCursor {
     pub fn new(pos: (usize, usize)) -> HexCursor {
-        HexCursor { pos }
+        HexCursor { pos, cols: 0x10 }
+    }
+
+    /// Change the number of bytes per row, staying on the same nibble
+    pub fn set_cols(&mut self, cols: usize) {
+        let nibble = self.pos.0 % 2;
+        let loc = self.loc();
+        self.cols = cols;
+        self.goto(loc);
+        self.pos.0 += nibble;
     }
 
     pub fn up(&mut self) {
@@ -75,15 +102,15 @@ impl HexCursor {
 
     pub fn down(&mut self, filesize: usize) {
         self.pos.1 += 1;
-        if (self.pos.1 * 0x10) + (self.pos.0 / 2) > filesize {
-            self.pos = ((filesize % 0x10) * 2, filesize / 0x10);
+        if (self.pos.1 * self.cols) + (self.pos.0 / 2) >= filesize {
+            self.pos = ((filesize % self.cols) * 2, filesize / self.cols);
             self.left();
         }
     }
 
     pub fn left(&mut self) {
         if self.pos.0 == 0 {
-            self.pos.0 = 0x1F;
+            self.pos.0 = self.cols * 2 - 1;
             self.up();
         } else {
             self.pos.0 -= 1;
@@ -91,11 +118,11 @@ impl HexCursor {
     }
 
     pub fn right(&mut self, filesize: usize) {
-        if ((self.pos.0 + 1) / 2) + (self.pos.1 * 0x10) == filesize {
+        if self.pos.0.div_ceil(2) + (self.pos.1 * self.cols) >= filesize {
             return;
         }
 
-        if self.pos.0 == 0x1F {
+        if self.pos.0 == self.cols * 2 - 1 {
             self.pos.0 = 0;
             self.down(filesize);
         } else {
@@ -104,13 +131,11 @@ impl HexCursor {
     }
 
     pub fn next_word(&mut self, filesize: usize) {
-        const WORD_LEN: usize = 4;
-
         let new_loc = (self.loc() + WORD_LEN) & !(WORD_LEN - 1);
-        let new_loc = usize::min(new_loc, filesize - 1);
+        let new_loc = usize::min(new_loc, filesize.saturating_sub(1));
 
-        let y = new_loc / 0x10;
-        let x = (new_loc % 0x10) * 2;
+        let y = new_loc / self.cols;
+        let x = (new_loc % self.cols) * 2;
 
         self.pos = (x, y);
     }
@@ -121,17 +146,25 @@ impl HexCursor {
             loc => loc & !(WORD_LEN - 1),
         };
 
-        let y = new_loc / 0x10;
-        let x = (new_loc % 0x10) * 2;
+        let y = new_loc / self.cols;
+        let x = (new_loc % self.cols) * 2;
 
         self.pos = (x, y);
     }
 
+    /// Snap the cursor back to the upper nibble of the append position if it moved
+    /// past the end of the file
+    pub fn clamp_append(&mut self, filesize: usize) {
+        if self.loc() >= filesize {
+            self.goto(filesize);
+        }
+    }
+
     pub fn goto(&mut self, loc: usize) {
-        self.pos = ((loc % 0x10) * 2, loc / 0x10)
+        self.pos = ((loc % self.cols) * 2, loc / self.cols)
     }
 
     pub fn loc(&self) -> usize {
-        (self.pos.0 / 2) + (self.pos.1 * 0x10)
+        (self.pos.0 / 2) + (self.pos.1 * self.cols)
     }
 }